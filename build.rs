@@ -1,3 +1,6 @@
 fn main() {
-    embuild::espidf::sysenv::output();
+    // Only the firmware needs the ESP-IDF environment
+    if std::env::var_os("CARGO_FEATURE_ESP").is_some() {
+        embuild::espidf::sysenv::output();
+    }
 }
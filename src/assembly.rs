@@ -1,189 +1,570 @@
-use std::arch::asm;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 
+use crate::assembly::executor::Software;
+use crate::assembly::history::Undo;
+use crate::assembly::parser::OperandKind::{Immediate as Imm, Label, Memory, Register as Reg};
+use crate::assembly::parser::{parse_line, Line, OperandKind};
 use crate::assembly::InstructionFormat::*;
 use crate::assembly::Register::*;
-use crate::assembly::RegisterOrOffset::*;
 use crate::assembly::SupportedInstruction::*;
 
-#[derive(Debug)]
+mod breakpoint;
+mod diagnostic;
+mod differential;
+mod disassembler;
+mod encoder;
+mod executor;
+mod history;
+mod lexer;
+mod parser;
+mod pseudo;
+mod snapshot;
+mod trace;
+
+pub use breakpoint::{Condition, StopReason};
+pub use diagnostic::{Diagnostic, DiagnosticKind};
+pub use differential::{Differential, Recorder, Replay};
+pub use disassembler::disassemble;
+pub use executor::{Backend, Executor};
+pub use snapshot::{Snapshot, SnapshotBreakpoint, SNAPSHOT_VERSION};
+pub use trace::{MemoryAccess, Trace, TraceEntry};
+
+#[derive(Debug, Clone, Copy)]
 enum SupportedInstruction {
     Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Sll,
+    Srl,
+    Sra,
+    Slt,
+    Sltu,
     AddI,
+    AndI,
+    OrI,
+    XorI,
+    SllI,
+    SrlI,
+    SraI,
+    SltI,
+    SltIU,
+    Lui,
+    AuiPc,
+    Beq,
+    Bne,
     Blt,
-    //   Comment,
-    //   Label, TODO later, they're also not instructions...
+    Bge,
+    Bltu,
+    Bgeu,
+    Jal,
+    Jalr,
+    Lb,
+    Lh,
+    Lw,
+    Lbu,
+    Lhu,
+    Sb,
+    Sh,
+    Sw,
 }
 
 impl SupportedInstruction {
+    const ALL: [SupportedInstruction; 37] = [
+        Add, Sub, And, Or, Xor, Sll, Srl, Sra, Slt, Sltu, AddI, AndI, OrI, XorI, SllI, SrlI, SraI,
+        SltI, SltIU, Lui, AuiPc, Beq, Bne, Blt, Bge, Bltu, Bgeu, Jal, Jalr, Lb, Lh, Lw, Lbu, Lhu,
+        Sb, Sh, Sw,
+    ];
+
+    fn from_str(mnemonic: &str) -> Result<Self, String> {
+        let lowercase = mnemonic.to_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|instruction| instruction.mnemonic() == lowercase)
+            .ok_or_else(|| {
+                format!("I only know a very limited amount of instructions and not '{mnemonic}'")
+            })
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Add => "add",
+            Sub => "sub",
+            And => "and",
+            Or => "or",
+            Xor => "xor",
+            Sll => "sll",
+            Srl => "srl",
+            Sra => "sra",
+            Slt => "slt",
+            Sltu => "sltu",
+            AddI => "addi",
+            AndI => "andi",
+            OrI => "ori",
+            XorI => "xori",
+            SllI => "slli",
+            SrlI => "srli",
+            SraI => "srai",
+            SltI => "slti",
+            SltIU => "sltiu",
+            Lui => "lui",
+            AuiPc => "auipc",
+            Beq => "beq",
+            Bne => "bne",
+            Blt => "blt",
+            Bge => "bge",
+            Bltu => "bltu",
+            Bgeu => "bgeu",
+            Jal => "jal",
+            Jalr => "jalr",
+            Lb => "lb",
+            Lh => "lh",
+            Lw => "lw",
+            Lbu => "lbu",
+            Lhu => "lhu",
+            Sb => "sb",
+            Sh => "sh",
+            Sw => "sw",
+        }
+    }
+
     pub fn info(&self) -> InstructionInfo {
+        let (format, opcode, funct3, funct7) = match self {
+            Add => (R, 0b0110011, 0b000, 0b0000000),
+            Sub => (R, 0b0110011, 0b000, 0b0100000),
+            Sll => (R, 0b0110011, 0b001, 0b0000000),
+            Slt => (R, 0b0110011, 0b010, 0b0000000),
+            Sltu => (R, 0b0110011, 0b011, 0b0000000),
+            Xor => (R, 0b0110011, 0b100, 0b0000000),
+            Srl => (R, 0b0110011, 0b101, 0b0000000),
+            Sra => (R, 0b0110011, 0b101, 0b0100000),
+            Or => (R, 0b0110011, 0b110, 0b0000000),
+            And => (R, 0b0110011, 0b111, 0b0000000),
+            AddI => (I, 0b0010011, 0b000, 0),
+            SllI => (I, 0b0010011, 0b001, 0b0000000), // funct7 ends up in the upper immediate bits
+            SltI => (I, 0b0010011, 0b010, 0),
+            SltIU => (I, 0b0010011, 0b011, 0),
+            XorI => (I, 0b0010011, 0b100, 0),
+            SrlI => (I, 0b0010011, 0b101, 0b0000000),
+            SraI => (I, 0b0010011, 0b101, 0b0100000),
+            OrI => (I, 0b0010011, 0b110, 0),
+            AndI => (I, 0b0010011, 0b111, 0),
+            Lui => (U, 0b0110111, 0, 0),
+            AuiPc => (U, 0b0010111, 0, 0),
+            Beq => (SB, 0b1100011, 0b000, 0),
+            Bne => (SB, 0b1100011, 0b001, 0),
+            Blt => (SB, 0b1100011, 0b100, 0),
+            Bge => (SB, 0b1100011, 0b101, 0),
+            Bltu => (SB, 0b1100011, 0b110, 0),
+            Bgeu => (SB, 0b1100011, 0b111, 0),
+            Jal => (UJ, 0b1101111, 0, 0),
+            Jalr => (I, 0b1100111, 0b000, 0),
+            Lb => (I, 0b0000011, 0b000, 0),
+            Lh => (I, 0b0000011, 0b001, 0),
+            Lw => (I, 0b0000011, 0b010, 0),
+            Lbu => (I, 0b0000011, 0b100, 0),
+            Lhu => (I, 0b0000011, 0b101, 0),
+            Sb => (S, 0b0100011, 0b000, 0),
+            Sh => (S, 0b0100011, 0b001, 0),
+            Sw => (S, 0b0100011, 0b010, 0),
+        };
+        InstructionInfo {
+            format,
+            opcode,
+            funct3,
+            funct7,
+        }
+    }
+
+    /// The operands the instruction expects, for error messages.
+    fn usage(&self) -> &'static str {
         match self {
-            Add => InstructionInfo {
-                format: R,
-                opcode: 0b0110011,
-                funct3: 0,
-                funct7: 0,
-            },
-            AddI => InstructionInfo {
-                format: I,
-                opcode: 0b0010011,
-                funct3: 0,
-                funct7: 0,
-            },
-            _ => panic!("Asked for info for an instruction that won't be really exectued!"),
+            Add | Sub | And | Or | Xor | Sll | Srl | Sra | Slt | Sltu => "rd, rs1, rs2",
+            AddI | AndI | OrI | XorI | SllI | SrlI | SraI | SltI | SltIU => "rd, rs1, imm",
+            Lui | AuiPc => "rd, imm",
+            Beq | Bne | Blt | Bge | Bltu | Bgeu => "rs1, rs2, label",
+            Jal => "rd, label",
+            Jalr => "rd, offset(rs1)",
+            Lb | Lh | Lw | Lbu | Lhu => "rd, offset(rs1)",
+            Sb | Sh | Sw => "rs2, offset(rs1)",
+        }
+    }
+
+    /// Whether the instruction is copied to and run on the hardware, instead of in software.
+    fn runs_on_hardware(&self) -> bool {
+        // The register-register, register-immediate and lui opcodes
+        matches!(self.info().opcode, 0b0110011 | 0b0010011 | 0b0110111)
+    }
+
+    fn is_shift_immediate(&self) -> bool {
+        matches!(self, SllI | SrlI | SraI)
+    }
+
+    /// Number of bytes a load or store accesses.
+    fn access_width(&self) -> u32 {
+        match self {
+            Lb | Lbu | Sb => 1,
+            Lh | Lhu | Sh => 2,
+            _ => 4,
         }
     }
 }
 
-#[derive(Debug)]
-enum Register {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Register {
     X0,
+    X1,
+    X2,
+    X3,
+    X4,
     X5,
     X6,
     X7,
+    X8,
+    X9,
+    X10,
+    X11,
+    X12,
+    X13,
+    X14,
+    X15,
+    X16,
+    X17,
+    X18,
+    X19,
+    X20,
+    X21,
+    X22,
+    X23,
+    X24,
+    X25,
+    X26,
+    X27,
     X28,
+    X29,
+    X30,
     X31,
 }
 
 impl Register {
-    fn parse(reg: &str) -> Result<Self, String> {
-        Self::from_str(reg.replace(',', "").trim())
+    pub const ALL: [Register; 32] = [
+        X0, X1, X2, X3, X4, X5, X6, X7, X8, X9, X10, X11, X12, X13, X14, X15, X16, X17, X18, X19,
+        X20, X21, X22, X23, X24, X25, X26, X27, X28, X29, X30, X31,
+    ];
+
+    pub fn to_code(self) -> i32 {
+        self as i32
     }
 
-    fn from_str(reg: &str) -> Result<Self, String> {
-        match reg {
-            "x0" => Ok(X0),
-            "x5" => Ok(X5),
-            "x6" => Ok(X6),
-            "x7" => Ok(X7),
-            "x28" => Ok(X28),
-            "x31" => Ok(X31),
-            _ => Err(format!(
-                "I only understand a limited number of registers, not '{}'",
-                reg
-            )),
+    pub fn abi_name(self) -> &'static str {
+        match self {
+            X0 => "zero",
+            X1 => "ra",
+            X2 => "sp",
+            X3 => "gp",
+            X4 => "tp",
+            X5 => "t0",
+            X6 => "t1",
+            X7 => "t2",
+            X8 => "s0/fp",
+            X9 => "s1",
+            X10 => "a0",
+            X11 => "a1",
+            X12 => "a2",
+            X13 => "a3",
+            X14 => "a4",
+            X15 => "a5",
+            X16 => "a6",
+            X17 => "a7",
+            X18 => "s2",
+            X19 => "s3",
+            X20 => "s4",
+            X21 => "s5",
+            X22 => "s6",
+            X23 => "s7",
+            X24 => "s8",
+            X25 => "s9",
+            X26 => "s10",
+            X27 => "s11",
+            X28 => "t3",
+            X29 => "t4",
+            X30 => "t5",
+            X31 => "t6",
         }
     }
 
-    fn to_code(&self) -> i16 {
+    /// Why the hardware can't run instructions using this register, if it can't.
+    fn hardware_reserved(self) -> Option<&'static str> {
         match self {
-            X0 => 0,
-            X5 => 5,
-            X6 => 6,
-            X7 => 7,
-            X28 => 28,
-            X31 => 31,
+            X1 => Some("it holds the return address back from the instruction"),
+            X2 => Some("it is the stack pointer of the firmware"),
+            X3 => Some("it is the global pointer of the firmware"),
+            X4 => Some("it is the thread pointer of the firmware"),
+            X8 => Some("it is the frame pointer of the firmware"),
+            X9 => Some("the compiler reserves it"),
+            X29 | X30 => Some("it is used to jump to the instruction"),
+            _ => None,
         }
     }
 }
 
-#[derive(Debug)]
-enum RegisterOrOffset {
-    Offset(i16),
-    Register_(Register),
-}
+impl FromStr for Register {
+    type Err = String;
 
-impl RegisterOrOffset {
-    fn to_code(&self) -> i16 {
-        match self {
-            Offset(n) => *n,
-            Register_(r) => r.to_code(),
+    /// Parses `x0` to `x31` and the ABI names like `sp` and `t0`.
+    fn from_str(reg: &str) -> Result<Self, String> {
+        if let Some(number) = reg.strip_prefix('x')
+            && let Ok(number) = number.parse::<usize>()
+            && number < 32
+        {
+            return Ok(Self::ALL[number]);
+        }
+        match reg {
+            "zero" => Ok(X0),
+            "ra" => Ok(X1),
+            "sp" => Ok(X2),
+            "gp" => Ok(X3),
+            "tp" => Ok(X4),
+            "t0" => Ok(X5),
+            "t1" => Ok(X6),
+            "t2" => Ok(X7),
+            "s0" | "fp" => Ok(X8),
+            "s1" => Ok(X9),
+            "a0" => Ok(X10),
+            "a1" => Ok(X11),
+            "a2" => Ok(X12),
+            "a3" => Ok(X13),
+            "a4" => Ok(X14),
+            "a5" => Ok(X15),
+            "a6" => Ok(X16),
+            "a7" => Ok(X17),
+            "s2" => Ok(X18),
+            "s3" => Ok(X19),
+            "s4" => Ok(X20),
+            "s5" => Ok(X21),
+            "s6" => Ok(X22),
+            "s7" => Ok(X23),
+            "s8" => Ok(X24),
+            "s9" => Ok(X25),
+            "s10" => Ok(X26),
+            "s11" => Ok(X27),
+            "t3" => Ok(X28),
+            "t4" => Ok(X29),
+            "t5" => Ok(X30),
+            "t6" => Ok(X31),
+            _ => Err(format!(
+                "'{reg}' is not a register, use x0 to x31 or their ABI names"
+            )),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 struct Instruction {
     instruction: SupportedInstruction,
     rd: Register, //Could be options but whatever
     rs1: Register,
-    offset_or_rs2: RegisterOrOffset,
+    rs2: Register,
+    imm: i32,
 }
 
 impl Instruction {
-    fn parse(line: &str) -> Result<Instruction, String> {
-        let line = line.trim();
-        //    TODO instuctions   if ["#", "//", "--"].iter().any(|x| line.starts_with(x)) {
-        //         return Ok(Instruction::comment());
-        //   }
-        //
-        let split1: Vec<&str> = line.split(' ').collect();
-        if split1.len() != 4 {
-            return Err(format!("Can't read '{line}'"));
-        }
+    /// Builds an instruction from a parsed line, `index` is its position in the program and is
+    /// used to turn label references into pc relative offsets. Registers the firmware needs are
+    /// only refused when `backend` has to send every instruction to the CPU.
+    fn build(
+        line: &Line,
+        index: usize,
+        labels: &Labels,
+        backend: Backend,
+    ) -> Result<Instruction, Diagnostic> {
+        let (mnemonic, mnemonic_span) = line.mnemonic.clone().unwrap_or_default();
+        let supported = SupportedInstruction::from_str(mnemonic)
+            .map_err(|e| Diagnostic::new(DiagnosticKind::UnknownInstruction, mnemonic_span, e))?;
+        Self::build_as(supported, line, index, labels, backend)
+    }
 
-        let supported;
-        let rd;
-        let rs1;
-        let offset_or_rs2;
-        match split1[0] {
-            "add" | "ADD" => {
-                supported = Add;
-                rd = Register::parse(split1[1])?;
-                rs1 = Register::parse(split1[2])?;
-                offset_or_rs2 = Register_(Register::parse(split1[3])?);
+    /// Builds `supported` from the operands of `line`, errors name the mnemonic `line` has, which
+    /// is the pseudo-instruction when `supported` is part of one.
+    fn build_as(
+        supported: SupportedInstruction,
+        line: &Line,
+        index: usize,
+        labels: &Labels,
+        backend: Backend,
+    ) -> Result<Instruction, Diagnostic> {
+        let (mnemonic, _) = line.mnemonic.clone().unwrap_or_default();
+        let operands = &line.operands;
+        let kinds: Vec<OperandKind> = operands.iter().map(|operand| operand.kind).collect();
+        let span = |i: usize| &operands[i].span;
+
+        let mut rd = X0;
+        let mut rs1 = X0;
+        let mut rs2 = X0;
+        let imm;
+        match (supported, kinds.as_slice()) {
+            (
+                Add | Sub | And | Or | Xor | Sll | Srl | Sra | Slt | Sltu,
+                [Reg(d), Reg(s1), Reg(s2)],
+            ) => {
+                (rd, rs1, rs2) = (*d, *s1, *s2);
+                imm = 0;
             }
-            "addi" | "ADDI" => {
-                supported = AddI;
-                rd = Register::parse(split1[1])?;
-                rs1 = Register::parse(split1[2])?;
-                let offset = split1[3]
-                    .parse()
-                    .map_err(|_| format!("Can't parse '{}' as an offset", split1[3]))?;
-                offset_or_rs2 = Offset(offset);
+            (SllI | SrlI | SraI, [Reg(d), Reg(s1), Imm(shamt)]) => {
+                (rd, rs1, imm) = (*d, *s1, check_range(*shamt, 0, 31, span(2))?);
             }
-            "blt" | "BLT" => {
-                supported = Blt;
-                rd = Register::parse(split1[1])?;
-                rs1 = Register::parse(split1[2])?;
-                let offset = split1[3]
-                    .parse()
-                    .map_err(|_| format!("Can't parse '{}' as an offset", split1[3]))?;
-                if !(-2048..=2047).contains(&offset) {
-                    return Err(format!("{offset} can only be between -2048 and 2047"));
-                }
-                offset_or_rs2 = Offset(offset);
+            (AddI | AndI | OrI | XorI | SltI | SltIU, [Reg(d), Reg(s1), Imm(offset)]) => {
+                (rd, rs1, imm) = (*d, *s1, check_range(*offset, -2048, 2047, span(2))?);
+            }
+            (Lui | AuiPc, [Reg(d), Imm(upper)]) => {
+                (rd, imm) = (*d, check_range(*upper, 0, 0xFFFFF, span(1))?);
+            }
+            (Beq | Bne | Blt | Bge | Bltu | Bgeu, [Reg(s1), Reg(s2), target]) => {
+                (rs1, rs2) = (*s1, *s2);
+                imm = parse_target(target, span(2), index, labels, 4096)?;
+            }
+            (Jal, [target]) => {
+                rd = X1;
+                imm = parse_target(target, span(0), index, labels, 1 << 20)?;
             }
-            x => {
-                return Err(format!(
-                    "I only know a very limited amount of instructions and not '{x}'"
+            (Jal, [Reg(d), target]) => {
+                rd = *d;
+                imm = parse_target(target, span(1), index, labels, 1 << 20)?;
+            }
+            (Jalr, [Reg(s1)]) => {
+                (rd, rs1, imm) = (X1, *s1, 0);
+            }
+            (Jalr | Lb | Lh | Lw | Lbu | Lhu, [Reg(d), Memory { offset, base }]) => {
+                (rd, rs1, imm) = (*d, *base, check_range(*offset, -2048, 2047, span(1))?);
+            }
+            (Jalr, [Reg(d), Reg(s1), Imm(offset)]) => {
+                (rd, rs1, imm) = (*d, *s1, check_range(*offset, -2048, 2047, span(2))?);
+            }
+            (Sb | Sh | Sw, [Reg(s2), Memory { offset, base }]) => {
+                (rs2, rs1, imm) = (*s2, *base, check_range(*offset, -2048, 2047, span(1))?);
+            }
+            _ => {
+                return Err(Diagnostic::new(
+                    DiagnosticKind::Operands,
+                    line.operands_span(),
+                    format!("'{mnemonic}' expects '{}'", supported.usage()),
                 ))
             }
         }
 
-        Ok(Instruction {
+        let instruction = Instruction {
             instruction: supported,
             rd,
             rs1,
-            offset_or_rs2,
-        })
+            rs2,
+            imm,
+        };
+        if backend.needs_cpu()
+            && let Some((register, reason)) = instruction.reserved_register()
+        {
+            let span = operands
+                .iter()
+                .find(|operand| match operand.kind {
+                    Reg(r) | Memory { base: r, .. } => r == register,
+                    _ => false,
+                })
+                .map_or(line.operands_span(), |operand| operand.span.clone());
+            return Err(Diagnostic::new(
+                DiagnosticKind::ReservedRegister,
+                span,
+                format!(
+                    "'{mnemonic}' can't be checked on the CPU, which can't use x{} ({}) because {reason}",
+                    register.to_code(),
+                    register.abi_name()
+                ),
+            )
+            .with_hint(
+                "t0-t3, t6, a0-a7 and s2-s11 are free to use, the other backends can use every register",
+            ));
+        }
+        instruction
+            .encode()
+            .map_err(|e| Diagnostic::new(DiagnosticKind::OutOfRange, line.operands_span(), e))?;
+        Ok(instruction)
     }
+}
 
-    pub fn to_code(&self) -> i32 {
-        let InstructionInfo {
-            format,
-            opcode,
-            funct3,
-            funct7,
-        } = self.instruction.info();
-        let (rd, rs1, rs2_or_imm) = (
-            self.rd.to_code(),
-            self.rs1.to_code(),
-            self.offset_or_rs2.to_code(),
-        );
-        match format {
-            R => i32::from_str_radix(
-                &format!("{funct7:07b}{rs2_or_imm:05b}{rs1:05b}{funct3:03b}{rd:05b}{opcode:07b}"),
-                2,
-            )
-            .unwrap(), //TODO bubble errors or we know parsed etc. here.
-            I => {
-                let rs2_or_imm = rs2_or_imm & 0xFFF; //Take only the last 12 bits.
-                let bin_str =
-                    &format!("{rs2_or_imm:012b}{rs1:05b}{funct3:03b}{rd:05b}{opcode:07b}");
-                u32::from_str_radix(bin_str, 2).unwrap() as i32
-            }
+impl Instruction {
+    /// A register the instruction would need on the CPU that the firmware keeps for itself, with
+    /// why.
+    fn reserved_register(&self) -> Option<(Register, &'static str)> {
+        if !self.instruction.runs_on_hardware() {
+            return None;
         }
+        [self.rd, self.rs1, self.rs2]
+            .into_iter()
+            .find_map(|register| Some((register, register.hardware_reserved()?)))
+    }
+}
+
+/// Label names and the index of the instruction they point to.
+type Labels<'a> = HashMap<&'a str, usize>;
+
+fn check_range(imm: i32, min: i32, max: i32, span: &Range<usize>) -> Result<i32, Diagnostic> {
+    if !(min..=max).contains(&imm) {
+        return Err(Diagnostic::new(
+            DiagnosticKind::OutOfRange,
+            span.clone(),
+            format!("{imm} can only be between {min} and {max}"),
+        ));
+    }
+    Ok(imm)
+}
+
+/// Parses a branch or jump target, either a label or a pc relative byte offset. The offset has to
+/// be even and fit in `-range..range`.
+fn parse_target(
+    target: &OperandKind,
+    span: &Range<usize>,
+    index: usize,
+    labels: &Labels,
+    range: i32,
+) -> Result<i32, Diagnostic> {
+    let offset = resolve_target(target, span, index, labels)?;
+    let offset = check_range(offset, -range, range - 1, span)?;
+    if offset % 2 != 0 {
+        return Err(Diagnostic::new(
+            DiagnosticKind::OutOfRange,
+            span.clone(),
+            format!("{offset} is not a multiple of 2 bytes"),
+        ));
+    }
+    Ok(offset)
+}
+
+/// Turns a label or a byte offset into the byte offset from the instruction at `index`.
+fn resolve_target(
+    target: &OperandKind,
+    span: &Range<usize>,
+    index: usize,
+    labels: &Labels,
+) -> Result<i32, Diagnostic> {
+    match target {
+        Label(label) => match labels.get(label) {
+            Some(&label_index) => Ok((label_index as i32 - index as i32) * 4),
+            None => Err(Diagnostic::new(
+                DiagnosticKind::UndefinedLabel,
+                span.clone(),
+                format!("Undefined label '{label}'"),
+            )
+            .with_hint("define it with 'name:' in front of an instruction")),
+        },
+        Imm(offset) => Ok(*offset),
+        _ => Err(Diagnostic::new(
+            DiagnosticKind::Operands,
+            span.clone(),
+            "Expected a label or an offset".to_string(),
+        )),
     }
 }
 
@@ -195,140 +576,464 @@ struct InstructionInfo {
     funct7: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 enum InstructionFormat {
     R,
     I,
-    //    SB,
+    S,
+    U,
+    SB,
+    UJ,
+}
+
+/// Address of the first byte of the data memory.
+pub const MEMORY_START: u32 = 0x1000_0000;
+
+/// Steps [`Interpreter::step_back`] can undo by default. An undo is about 28 bytes and the
+/// ESP32-C3 has about 400 KB of RAM for everything, up to five sessions included.
+#[cfg(target_arch = "riscv32")]
+const DEFAULT_HISTORY: usize = 256;
+#[cfg(not(target_arch = "riscv32"))]
+const DEFAULT_HISTORY: usize = 10_000;
+/// Steps a trace records by default, a step is about 48 bytes.
+#[cfg(target_arch = "riscv32")]
+const DEFAULT_TRACE_LIMIT: usize = 256;
+#[cfg(not(target_arch = "riscv32"))]
+const DEFAULT_TRACE_LIMIT: usize = 100_000;
+
+/// Settings for a new [`Interpreter`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Size in bytes of the data memory starting at [`MEMORY_START`].
+    pub memory_size: usize,
+    /// What runs the arithmetic and logic instructions.
+    pub backend: Backend,
+    /// Instructions the program may run in total, `None` for no limit.
+    pub step_budget: Option<u64>,
+    /// Time the program may spend running instructions in total, `None` for no limit. Keeps a
+    /// loop that never ends from holding up the server.
+    pub time_limit: Option<Duration>,
+    /// Steps [`Interpreter::step_back`] can undo, the oldest are forgotten first. Room for all of
+    /// them is taken when the program is assembled.
+    pub history: usize,
+    /// Steps a trace records, the ones after that are left out and the trace is marked as
+    /// [`Trace::truncated`].
+    pub trace_limit: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            memory_size: 4096,
+            backend: Backend::default(),
+            step_budget: Some(1_000_000),
+            time_limit: Some(Duration::from_secs(5)),
+            history: DEFAULT_HISTORY,
+            trace_limit: DEFAULT_TRACE_LIMIT,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Interpreter {
     pub line: usize,
+    /// What the program was assembled from, for snapshots.
+    source: Vec<String>,
     program: Vec<Instruction>,
+    /// The line in the source each instruction in `program` came from, starting at 1.
+    source_lines: Vec<usize>,
     //    instruction_info: &'static HashMap<Instruction, InstructionInfo>,
     pub registers: [i32; 31],
+    memory: Vec<u8>,
+    executor: Box<dyn Executor>,
+    /// Source lines, starting at 1, with their condition.
+    breakpoints: BTreeMap<usize, Option<Condition>>,
+    /// `steps` when [`Interpreter::resume`] last stopped at a breakpoint, so resuming from there
+    /// doesn't stop at it again.
+    breakpoint_hit: Option<u64>,
+    watchpoints: Vec<Register>,
+    /// Instructions run so far.
+    steps: u64,
+    /// Time spent running them.
+    run_time: Duration,
+    step_budget: Option<u64>,
+    time_limit: Option<Duration>,
+    /// Newest last.
+    history: VecDeque<Undo>,
+    history_size: usize,
+    /// Recorded while `Some`.
+    trace: Option<Trace>,
+    trace_limit: usize,
 }
 
 impl Interpreter {
-    pub fn new(in_program: Vec<String>) -> Result<Self, String> {
+    pub fn new(in_program: Vec<String>) -> Result<Self, Vec<Diagnostic>> {
+        Self::with_config(in_program, Config::default())
+    }
+
+    pub fn with_config(in_program: Vec<String>, config: Config) -> Result<Self, Vec<Diagnostic>> {
+        let executor = config.backend.executor();
+        Self::with_executor(in_program, config, executor)
+    }
+
+    /// Assembles the program, or returns everything that's wrong with it. `executor` runs the
+    /// instructions sent to the CPU instead of `config.backend`.
+    pub fn with_executor(
+        in_program: Vec<String>,
+        config: Config,
+        executor: Box<dyn Executor>,
+    ) -> Result<Self, Vec<Diagnostic>> {
+        let mut diagnostics = Vec::new();
+
+        // First pass finds where the labels point to, so branches can refer to labels further on.
+        let mut labels = Labels::new();
+        let mut label_lines = HashMap::new();
+        let mut instructions = Vec::new();
+        let mut index = 0;
+        for (i, line) in in_program.iter().enumerate() {
+            let i = i + 1; // Editors count from 1
+            let parsed = match parse_line(line) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    diagnostics.push(e.on_line(i));
+                    continue;
+                }
+            };
+            if let Some((label, span)) = &parsed.label {
+                if let Some(defined_on) = label_lines.insert(*label, i) {
+                    diagnostics.push(
+                        Diagnostic::new(
+                            DiagnosticKind::DuplicateLabel,
+                            span.clone(),
+                            format!("Label '{label}' is already defined on line {defined_on}"),
+                        )
+                        .on_line(i),
+                    );
+                } else {
+                    labels.insert(label, index);
+                }
+            }
+            if parsed.mnemonic.is_some() {
+                let size = pseudo::size(&parsed);
+                instructions.push((i, index, parsed));
+                index += size;
+            }
+        }
+
         let mut program = Vec::new();
-        for (i, line) in in_program.into_iter().enumerate() {
-            match Instruction::parse(&line) {
-                Ok(instruction) => program.push(instruction),
-                Err(e) => return Err(format!("Error on line {i}: {e}")),
+        let mut source_lines = Vec::new();
+        for (i, index, line) in instructions {
+            match pseudo::expand(&line, index, &labels, config.backend) {
+                Ok(expanded) => {
+                    // Every instruction of a pseudo-instruction steps on the same line
+                    source_lines.extend(expanded.iter().map(|_| i));
+                    program.extend(expanded);
+                }
+                Err(e) => diagnostics.push(e.on_line(i)),
             }
         }
 
-        Ok(Self {
+        if !diagnostics.is_empty() {
+            diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+            return Err(diagnostics);
+        }
+        let mut interpreter = Self {
             line: 0,
+            source: in_program,
             program,
+            source_lines,
             registers: [0; 31],
-        })
+            memory: vec![0; config.memory_size],
+            executor,
+            breakpoints: BTreeMap::new(),
+            breakpoint_hit: None,
+            watchpoints: Vec::new(),
+            steps: 0,
+            run_time: Duration::ZERO,
+            step_budget: config.step_budget,
+            time_limit: config.time_limit,
+            history: VecDeque::with_capacity(config.history),
+            history_size: config.history,
+            trace: None,
+            trace_limit: config.trace_limit,
+        };
+        // The stack grows down from the end of the data memory
+        let stack = MEMORY_START.wrapping_add(config.memory_size as u32);
+        interpreter.set_register(&X2, stack as i32);
+        Ok(interpreter)
     }
 
-    pub fn step(&mut self) -> Option<()> {
-        if self.line >= self.program.len() {
-            return None;
+    /// Runs the next instruction, `None` when the program finished or used up its budget, see
+    /// [`Interpreter::budget_exhausted`].
+    pub fn step(&mut self) -> Result<Option<()>, String> {
+        if self.line >= self.program.len() || self.budget_exhausted() {
+            return Ok(None);
+        }
+        let undo = self.undo();
+        let entry = self.trace_entry();
+        let started = Instant::now();
+        let result = self.execute();
+        self.run_time += started.elapsed();
+        if result.is_ok() {
+            self.steps += 1;
+            self.remember(undo);
+            if let Some(entry) = entry {
+                self.record(entry);
+            }
         }
+        result
+    }
+
+    /// Whether the program ran all the instructions or all the time `Config` allows.
+    pub fn budget_exhausted(&self) -> bool {
+        self.step_budget.is_some_and(|budget| self.steps >= budget)
+            || self.time_limit.is_some_and(|limit| self.run_time >= limit)
+    }
+
+    /// Instructions run so far, less the ones stepped back over.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
 
-        let instruction = &self.program[self.line];
+    fn execute(&mut self) -> Result<Option<()>, String> {
+        let instruction = self.program[self.line];
+        let pc = (self.line * 4) as i32;
         match instruction.instruction {
-            Add | AddI => self.real_step(instruction.to_code()),
-            Blt => {
-                if let Offset(n) = instruction.offset_or_rs2
-                    && self.registers(&instruction.rd) < self.registers(&instruction.rs1) + 1
-                {
-                    //let n = if n < 0 { n - 1 } else { n }; //Have to subtract current line too.
-                    self.line = (self.line as i64 + n as i64) as usize;
-                    //if self.line >= self.program.len() {
-                    //    return None;
-                    //}
-                    return Some(());
+            AuiPc => {
+                // The hardware would see the address of our scratch buffer, so use the program's pc.
+                self.set_register(&instruction.rd, pc.wrapping_add(instruction.imm << 12));
+            }
+            Beq | Bne | Blt | Bge | Bltu | Bgeu => {
+                let rs1 = self.registers(&instruction.rs1);
+                let rs2 = self.registers(&instruction.rs2);
+                let taken = match instruction.instruction {
+                    Beq => rs1 == rs2,
+                    Bne => rs1 != rs2,
+                    Blt => rs1 < rs2,
+                    Bge => rs1 >= rs2,
+                    Bltu => (rs1 as u32) < (rs2 as u32),
+                    _ => (rs1 as u32) >= (rs2 as u32),
+                };
+                if taken {
+                    return self.jump(pc.wrapping_add(instruction.imm));
                 }
             }
+            Jal => {
+                // Checked before linking, a jump that fails changes nothing
+                let target = self.jump_target(pc.wrapping_add(instruction.imm))?;
+                self.set_register(&instruction.rd, pc + 4);
+                self.line = target;
+                return Ok(Some(()));
+            }
+            Lb | Lh | Lw | Lbu | Lhu => {
+                // Loads and stores stay in software, programs only get to touch their own memory.
+                let range = self.access_range(&instruction)?;
+                let mut bytes = [0; 4];
+                bytes[..range.len()].copy_from_slice(&self.memory[range]);
+                let value = match instruction.instruction {
+                    Lb => bytes[0] as i8 as i32,
+                    Lh => i16::from_le_bytes([bytes[0], bytes[1]]) as i32,
+                    _ => i32::from_le_bytes(bytes), // Zero extended for lbu and lhu
+                };
+                self.set_register(&instruction.rd, value);
+            }
+            Sb | Sh | Sw => {
+                let range = self.access_range(&instruction)?;
+                let bytes = self.registers(&instruction.rs2).to_le_bytes();
+                let width = range.len();
+                self.memory[range].copy_from_slice(&bytes[..width]);
+            }
+            Jalr => {
+                // Read rs1 before writing rd, they can be the same register.
+                let target = self
+                    .registers(&instruction.rs1)
+                    .wrapping_add(instruction.imm)
+                    & !1;
+                let target = self.jump_target(target)?;
+                self.set_register(&instruction.rd, pc + 4);
+                self.line = target;
+                return Ok(Some(()));
+            }
+            // The CPU can't touch the firmware's registers, those run in software instead
+            _ if instruction.reserved_register().is_some() => {
+                Software.execute(instruction.encode()?, &mut self.registers)?
+            }
+            _ => self
+                .executor
+                .execute(instruction.encode()?, &mut self.registers)?,
         }
 
         self.line += 1;
-        Some(())
+        Ok(Some(()))
+    }
+
+    /// Machine code of the instruction that will be executed next.
+    pub fn current_encoding(&self) -> Option<u32> {
+        self.program
+            .get(self.line)
+            .and_then(|instruction| instruction.encode().ok())
+    }
+
+    /// Source line, starting at 1, of the instruction that will be executed next.
+    pub fn source_line(&self) -> Option<usize> {
+        self.source_lines.get(self.line).copied()
+    }
+
+    /// Moves execution to the instruction at byte address `target`, jumping to just past the last
+    /// instruction ends the program.
+    fn jump(&mut self, target: i32) -> Result<Option<()>, String> {
+        self.line = self.jump_target(target)?;
+        Ok(Some(()))
+    }
+
+    /// The index of the instruction at byte address `target`, if a jump can go there.
+    fn jump_target(&self, target: i32) -> Result<usize, String> {
+        if target % 4 != 0 {
+            return Err(format!("Jump to misaligned address {target:#x}"));
+        }
+        if target < 0 || target as usize / 4 > self.program.len() {
+            return Err(format!(
+                "Jump to {target:#x} which is outside of the program"
+            ));
+        }
+        Ok(target as usize / 4)
+    }
+
+    /// Checks an access to `address` is aligned and inside the data memory and returns the
+    /// accessed bytes' indices into it.
+    fn memory_range(
+        &self,
+        address: u32,
+        instruction: SupportedInstruction,
+    ) -> Result<Range<usize>, String> {
+        let width = instruction.access_width();
+        if address % width != 0 {
+            return Err(format!(
+                "Misaligned {width} byte access at {address:#x}, it should be a multiple of {width}"
+            ));
+        }
+        let end = MEMORY_START as usize + self.memory.len();
+        match address.checked_sub(MEMORY_START) {
+            Some(start) if start as usize + width as usize <= self.memory.len() => {
+                Ok(start as usize..(start + width) as usize)
+            }
+            _ => Err(format!(
+                "Address {address:#x} is outside of the data memory ({MEMORY_START:#x} to {end:#x})"
+            )),
+        }
+    }
+
+    /// The bytes a load reads or a store writes.
+    fn access_range(&self, instruction: &Instruction) -> Result<Range<usize>, String> {
+        let address = self
+            .registers(&instruction.rs1)
+            .wrapping_add(instruction.imm);
+        self.memory_range(address as u32, instruction.instruction)
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
     }
 
     fn registers(&self, register: &Register) -> i32 {
         match register {
             X0 => 0,
-            X5 => self.registers[4],
-            X6 => self.registers[5],
-            X7 => self.registers[6],
-            X28 => self.registers[27],
-            X31 => self.registers[30],
+            r => self.registers[r.to_code() as usize - 1],
+        }
+    }
+
+    fn set_register(&mut self, register: &Register, value: i32) {
+        match register {
+            X0 => {}
+            r => self.registers[r.to_code() as usize - 1] = value,
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Interpreter, StopReason};
+
+    fn interpreter(program: &[&str]) -> Interpreter {
+        with_budget(program, Config::default().step_budget)
+    }
 
-    fn real_step(&mut self, code: i32) {
-        #[allow(unused_mut)]
-        #[allow(unused_variables)]
-        #[allow(unused_assignments)]
-        let [mut x1, mut x2, mut x3, mut x4, mut x5, mut x6, mut x7, mut x8, mut x9, mut x10, mut x11, mut x12, mut x13, mut x14, mut x15, mut x16, mut x17, mut x18, mut x19, mut x20, mut x21, mut x22, mut x23, mut x24, mut x25, mut x26, mut x27, mut x28, mut x29, mut x30, mut x31] =
-            self.registers;
-
-        let instruction: [i32; 2] = [
-            code,
-            0b00000000000000001000000001100111, //jalr x0, 0(x1)
-        ];
-
-        let instruction_pointer: *const [i32; 2] = &instruction;
-        x30 = instruction_pointer as i32;
-
-        // Oh hmm DRAM vs IRAM https://www.espressif.com/sites/default/files/documentation/esp32-c3_technical_reference_manual_en.pdf#sysmem
-        let diff_data_and_instruction_bus = 0x70_0000;
-        x29 = diff_data_and_instruction_bus;
-
-        unsafe {
-            asm!(
-            "addi sp, sp, -4", // Add space on stack
-            "sw x1, 0(sp)", // Store return address on stack
-            "add x30, x30, x29", //Up the data bus known address to get the shadowed
-                                 //instruction bus address.
-            "jalr x1, 0(x30)",
-            "lw x1, 0(sp)", // Restore return address
-            "addi sp, sp, 4", //pop!
-
-            // Only the temporaries allow input (for the end-user not the code here), looks like we take off two (x29 and x30) from six, pfew.... Maybe
-            // misuse argument registers? Or something the **caller** should have saved? Check
-            // it.
-            // Not all registers can be used as out...
-            out("x1") x1,
-            inout("x5") x5,
-            inout("x6") x6,
-            inout("x7") x7,
-            out("x10") x10,
-            out("x11") x11,
-            out("x12") x12,
-            out("x13") x13,
-            out("x14") x14,
-            out("x15") x15,
-            out("x16") x16,
-            out("x17") x17,
-            out("x18") x18,
-            out("x19") x19,
-            out("x20") x20,
-            out("x21") x21,
-            out("x22") x22,
-            out("x23") x23,
-            out("x24") x24,
-            out("x25") x25,
-            out("x26") x26,
-            out("x27") x27,
-            inout("x28") x28,
-            inout("x29") x29,
-            inout("x30") x30,
-            inout("x31") x31,
-            );
+    fn with_budget(program: &[&str], step_budget: Option<u64>) -> Interpreter {
+        let program = program.iter().map(|line| line.to_string()).collect();
+        let config = Config {
+            step_budget,
+            ..Config::default()
+        };
+        Interpreter::with_config(program, config).unwrap()
+    }
+
+    #[test]
+    fn failed_jumps_do_not_link() {
+        for (program, register, value, error) in [
+            (
+                &["li ra, 5", "jalr ra, 3(zero)"][..],
+                1,
+                5,
+                "Jump to misaligned address 0x2",
+            ),
+            (
+                &["li t0, 5", "jal t0, 400"][..],
+                5,
+                5,
+                "Jump to 0x194 which is outside of the program",
+            ),
+        ] {
+            let mut interpreter = interpreter(program);
+            interpreter.step().unwrap();
+            assert_eq!(interpreter.step(), Err(error.to_string()));
+            assert_eq!(interpreter.registers[register - 1], value, "{program:?}");
+            assert_eq!(interpreter.source_line(), Some(2));
         }
+    }
+
+    #[test]
+    fn jumps_link() {
+        let mut interpreter = interpreter(&["jal t0, 8", "nop", "jalr t1, 0(t0)", "nop"]);
+        interpreter.step().unwrap();
+        assert_eq!(interpreter.registers[4], 4);
+        assert_eq!(interpreter.source_line(), Some(3));
+        interpreter.step().unwrap();
+        assert_eq!(interpreter.registers[5], 12);
+        assert_eq!(interpreter.source_line(), Some(2));
+    }
+
+    const THREE: [&str; 3] = ["addi t0, t0, 1", "addi t0, t0, 1", "addi t0, t0, 1"];
+
+    #[test]
+    fn finishing_on_the_last_budgeted_step_is_finishing() {
+        let mut interpreter = with_budget(&THREE, Some(3));
+        for _ in 0..3 {
+            assert_eq!(interpreter.step(), Ok(Some(())));
+        }
+        // The budget is used up too, but nothing was left to run
+        assert!(interpreter.budget_exhausted());
+        assert_eq!(interpreter.step(), Ok(None));
+        assert_eq!(interpreter.source_line(), None);
+
+        let mut interpreter = with_budget(&THREE, Some(3));
+        assert_eq!(interpreter.resume(), Ok(StopReason::Finished));
+        assert_eq!(interpreter.registers[4], 3);
+    }
+
+    #[test]
+    fn running_out_of_budget() {
+        let mut interpreter = with_budget(&THREE, Some(2));
+        assert_eq!(interpreter.resume(), Ok(StopReason::BudgetExhausted));
+        assert_eq!(interpreter.source_line(), Some(3));
+        assert_eq!(interpreter.step(), Ok(None));
+        assert_eq!(interpreter.steps(), 2);
+
+        let mut interpreter = with_budget(&THREE, Some(0));
+        assert_eq!(interpreter.step(), Ok(None));
+        assert_eq!(interpreter.source_line(), Some(1));
 
-        self.registers = [
-            x1, x2, x3, x4, x5, x6, x7, x8, x9, x10, x11, x12, x13, x14, x15, x16, x17, x18, x19,
-            x20, x21, x22, x23, x24, x25, x26, x27, x28, x29, x30, x31,
-        ];
+        let mut interpreter = with_budget(&THREE, None);
+        assert_eq!(interpreter.resume(), Ok(StopReason::Finished));
+        assert!(!interpreter.budget_exhausted());
     }
 }
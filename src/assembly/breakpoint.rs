@@ -0,0 +1,339 @@
+//! Running until something interesting happens: a line with a breakpoint, optionally only when
+//! its condition holds, a watched register changing or the end of the program.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::assembly::{Interpreter, Register};
+
+/// Why [`Interpreter::resume`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Finished,
+    /// Ran all the instructions or time `Config` allows, see [`Interpreter::budget_exhausted`].
+    BudgetExhausted,
+    /// About to run the first instruction on `line`, starting at 1.
+    Breakpoint {
+        line: usize,
+    },
+    Watchpoint {
+        register: Register,
+        old: i32,
+        new: i32,
+    },
+}
+
+/// Compares a register with a number or another register, like `x5 == 10` or `t0 < t1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    register: Register,
+    comparison: Comparison,
+    value: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    /// Longest first, so `<=` isn't taken for `<`.
+    const ALL: [Comparison; 6] = [
+        Comparison::Equal,
+        Comparison::NotEqual,
+        Comparison::LessOrEqual,
+        Comparison::GreaterOrEqual,
+        Comparison::Less,
+        Comparison::Greater,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Register(Register),
+    Immediate(i32),
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(condition: &str) -> Result<Self, String> {
+        let (left, comparison, right) = Comparison::ALL
+            .into_iter()
+            .find_map(|comparison| {
+                let (left, right) = condition.split_once(comparison.as_str())?;
+                Some((left.trim(), comparison, right.trim()))
+            })
+            .ok_or_else(|| {
+                format!("'{condition}' is not a condition, write it like 'x5 == 10' with ==, !=, <, <=, > or >=")
+            })?;
+        let register = Register::from_str(left)?;
+        let value = match Register::from_str(right) {
+            Ok(register) => Value::Register(register),
+            Err(_) => Value::Immediate(
+                parse_number(right)
+                    .ok_or_else(|| format!("'{right}' is not a register or a number"))?,
+            ),
+        };
+        Ok(Self {
+            register,
+            comparison,
+            value,
+        })
+    }
+}
+
+/// Decimal or `0x` prefixed hexadecimal, from -2147483648 up to 0xFFFFFFFF like in the assembly.
+fn parse_number(number: &str) -> Option<i32> {
+    let (negative, digits) = match number.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, number),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i64>().ok()?,
+    };
+    if negative {
+        i32::try_from(-value).ok()
+    } else {
+        // Above i32::MAX it's the bit pattern that counts
+        i32::try_from(value)
+            .or_else(|_| u32::try_from(value).map(|value| value as i32))
+            .ok()
+    }
+}
+
+impl Condition {
+    /// Compares signed, like `blt` and `bge`.
+    fn holds(&self, interpreter: &Interpreter) -> bool {
+        let left = interpreter.registers(&self.register);
+        let right = match self.value {
+            Value::Register(register) => interpreter.registers(&register),
+            Value::Immediate(value) => value,
+        };
+        match self.comparison {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} ", self.register, self.comparison.as_str())?;
+        match self.value {
+            Value::Register(register) => write!(f, "{register}"),
+            Value::Immediate(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+impl Interpreter {
+    /// Stops [`Interpreter::resume`] before `line`, starting at 1, when `condition` holds or
+    /// always without one. Replaces a breakpoint already on the line.
+    pub fn add_breakpoint(&mut self, line: usize, condition: Option<&str>) -> Result<(), String> {
+        if !self.source_lines.contains(&line) {
+            return Err(format!("Line {line} has no instruction to stop at"));
+        }
+        let condition = condition.map(Condition::from_str).transpose()?;
+        self.breakpoints.insert(line, condition);
+        Ok(())
+    }
+
+    /// Returns false when there was no breakpoint on `line`.
+    pub fn remove_breakpoint(&mut self, line: usize) -> bool {
+        self.breakpoints.remove(&line).is_some()
+    }
+
+    /// Source lines with a breakpoint and their condition, if any.
+    pub fn breakpoints(&self) -> &BTreeMap<usize, Option<Condition>> {
+        &self.breakpoints
+    }
+
+    /// Stops [`Interpreter::resume`] after an instruction changes `register`.
+    pub fn watch(&mut self, register: Register) {
+        if !self.watchpoints.contains(&register) {
+            self.watchpoints.push(register);
+        }
+    }
+
+    /// Returns false when `register` wasn't watched.
+    pub fn unwatch(&mut self, register: Register) -> bool {
+        let watched = self.watchpoints.len();
+        self.watchpoints.retain(|watched| *watched != register);
+        self.watchpoints.len() != watched
+    }
+
+    pub fn watchpoints(&self) -> &[Register] {
+        &self.watchpoints
+    }
+
+    /// Runs until a breakpoint, a watchpoint, the end of the program or of its budget. A
+    /// breakpoint on the line it starts on stops it right away, unless it stopped there last
+    /// time. On an error [`Interpreter::source_line`] is the line that failed.
+    pub fn resume(&mut self) -> Result<StopReason, String> {
+        if self.breakpoint_hit.take() != Some(self.steps)
+            && let Some(line) = self.at_breakpoint()
+        {
+            self.breakpoint_hit = Some(self.steps);
+            return Ok(StopReason::Breakpoint { line });
+        }
+        loop {
+            let before: Vec<i32> = self
+                .watchpoints
+                .iter()
+                .map(|register| self.registers(register))
+                .collect();
+            if self.step()?.is_none() {
+                if self.source_line().is_some() {
+                    return Ok(StopReason::BudgetExhausted);
+                }
+                return Ok(StopReason::Finished);
+            }
+
+            for (register, old) in self.watchpoints.iter().zip(before) {
+                let new = self.registers(register);
+                if new != old {
+                    return Ok(StopReason::Watchpoint {
+                        register: *register,
+                        old,
+                        new,
+                    });
+                }
+            }
+
+            if self.source_line().is_none() {
+                return Ok(StopReason::Finished);
+            }
+            if let Some(line) = self.at_breakpoint() {
+                self.breakpoint_hit = Some(self.steps);
+                return Ok(StopReason::Breakpoint { line });
+            }
+        }
+    }
+
+    /// The line of the breakpoint the next instruction stops at, if any.
+    fn at_breakpoint(&self) -> Option<usize> {
+        let line = self.source_line()?;
+        // Not halfway through the instructions of a pseudo-instruction
+        let line_start =
+            self.line == 0 || self.source_lines[self.line - 1] != self.source_lines[self.line];
+        let condition = self.breakpoints.get(&line)?;
+        (line_start && condition.iter().all(|condition| condition.holds(self))).then_some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{Condition, StopReason};
+    use crate::assembly::{Interpreter, Register};
+
+    #[test]
+    fn numbers_from_i32_min_to_u32_max() {
+        for (text, shown) in [
+            ("x5 == -2147483648", "x5 == -2147483648"),
+            ("x5 == 0x80000000", "x5 == -2147483648"),
+            ("x5 == 0xFFFFFFFF", "x5 == -1"),
+            ("x5 == 4294967295", "x5 == -1"),
+            ("t0 < 2147483647", "x5 < 2147483647"),
+            ("t0 >= -0x10", "x5 >= -16"),
+        ] {
+            let condition = Condition::from_str(text).unwrap();
+            assert_eq!(condition.to_string(), shown);
+            // What's shown has to parse again, snapshots save conditions like that
+            assert_eq!(Condition::from_str(shown).unwrap(), condition, "{text}");
+        }
+    }
+
+    #[test]
+    fn numbers_out_of_range() {
+        for number in ["4294967296", "0x100000000", "-2147483649", "-0x80000001"] {
+            assert_eq!(
+                Condition::from_str(&format!("x5 == {number}")),
+                Err(format!("'{number}' is not a register or a number"))
+            );
+        }
+    }
+
+    fn interpreter(program: &[&str]) -> Interpreter {
+        Interpreter::new(program.iter().map(|line| line.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn stops_at_the_first_line() {
+        let mut interpreter = interpreter(&["addi t0, t0, 1", "addi t0, t0, 1"]);
+        interpreter.add_breakpoint(1, None).unwrap();
+        assert_eq!(interpreter.resume(), Ok(StopReason::Breakpoint { line: 1 }));
+        assert_eq!(interpreter.steps(), 0);
+        // Carries on from where it stopped
+        assert_eq!(interpreter.resume(), Ok(StopReason::Finished));
+        assert_eq!(interpreter.registers[4], 2);
+    }
+
+    #[test]
+    fn stops_every_time_around_a_loop() {
+        let mut interpreter =
+            interpreter(&["li t1, 3", "loop: addi t0, t0, 1", "blt t0, t1, loop"]);
+        interpreter.add_breakpoint(2, None).unwrap();
+        for t0 in 0..3 {
+            assert_eq!(interpreter.resume(), Ok(StopReason::Breakpoint { line: 2 }));
+            assert_eq!(interpreter.registers[4], t0);
+        }
+        assert_eq!(interpreter.resume(), Ok(StopReason::Finished));
+    }
+
+    #[test]
+    fn stops_where_it_stepped_to() {
+        let mut interpreter = interpreter(&["addi t0, t0, 1", "addi t0, t0, 1", "nop"]);
+        interpreter.add_breakpoint(2, None).unwrap();
+        interpreter.step().unwrap();
+        assert_eq!(interpreter.resume(), Ok(StopReason::Breakpoint { line: 2 }));
+        assert_eq!(interpreter.steps(), 1);
+    }
+
+    #[test]
+    fn conditions_and_watchpoints() {
+        let mut interpreter = interpreter(&[
+            "li t1, 5",
+            "loop: addi t0, t0, 1",
+            "blt t0, t1, loop",
+            "addi t2, t2, 1",
+        ]);
+        interpreter.add_breakpoint(2, Some("t0 == 3")).unwrap();
+        assert_eq!(interpreter.resume(), Ok(StopReason::Breakpoint { line: 2 }));
+        assert_eq!(interpreter.registers[4], 3);
+        interpreter.remove_breakpoint(2);
+        interpreter.watch(Register::X7);
+        assert_eq!(
+            interpreter.resume(),
+            Ok(StopReason::Watchpoint {
+                register: Register::X7,
+                old: 0,
+                new: 1
+            })
+        );
+    }
+}
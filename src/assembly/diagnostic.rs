@@ -0,0 +1,83 @@
+//! Problems found while assembling a program, precise enough for an editor to underline them.
+
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagnosticKind {
+    /// The line doesn't follow the grammar, like a missing `)` or a stray character.
+    Syntax,
+    UnknownInstruction,
+    /// The operands don't match what the instruction expects.
+    Operands,
+    OutOfRange,
+    UndefinedLabel,
+    DuplicateLabel,
+    /// A register the hardware can't run instructions with.
+    ReservedRegister,
+}
+
+impl DiagnosticKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiagnosticKind::Syntax => "syntax",
+            DiagnosticKind::UnknownInstruction => "unknown_instruction",
+            DiagnosticKind::Operands => "operands",
+            DiagnosticKind::OutOfRange => "out_of_range",
+            DiagnosticKind::UndefinedLabel => "undefined_label",
+            DiagnosticKind::DuplicateLabel => "duplicate_label",
+            DiagnosticKind::ReservedRegister => "reserved_register",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// Line in the source, starting at 1.
+    pub line: usize,
+    /// Byte offsets in the line of the offending text, starting at 0.
+    pub columns: Range<usize>,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    /// A diagnostic for the line that is being assembled, [`Diagnostic::on_line`] fills in the
+    /// line number.
+    pub(super) fn new(kind: DiagnosticKind, columns: Range<usize>, message: String) -> Self {
+        Self {
+            kind,
+            line: 0,
+            columns,
+            message,
+            hint: None,
+        }
+    }
+
+    pub(super) fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub(super) fn on_line(mut self, line: usize) -> Self {
+        self.line = line;
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Error on line {}:{}: {}",
+            self.line,
+            self.columns.start + 1,
+            self.message
+        )?;
+        if let Some(hint) = &self.hint {
+            write!(f, " ({hint})")?;
+        }
+        Ok(())
+    }
+}
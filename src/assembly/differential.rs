@@ -0,0 +1,351 @@
+//! Checks the CPU against the [`Software`] model of the instructions, and records what the CPU
+//! did so the check can be replayed on a computer without the chip.
+//!
+//! A recording has one line per instruction: `record`, the instruction word and x1 to x31 before
+//! and after it ran.
+//!
+//! ```text
+//! record 0x006283b3 0,0,0,0,-7,3,0,... -> 0,0,0,0,-7,3,-4,...
+//! ```
+//!
+//! Lines that don't start with `record` are skipped, so a whole serial log can be replayed.
+
+use std::fmt::{self, Debug};
+use std::io::Write;
+use std::vec::IntoIter;
+
+use crate::assembly::executor::Software;
+use crate::assembly::InstructionFormat::*;
+use crate::assembly::{Executor, Instruction};
+
+/// Runs every instruction on both `native` and the software model and fails the step when they
+/// don't end up with the same registers.
+#[derive(Debug)]
+pub struct Differential {
+    native: Box<dyn Executor>,
+    reference: Software,
+}
+
+impl Differential {
+    pub fn new(native: Box<dyn Executor>) -> Self {
+        Self {
+            native,
+            reference: Software,
+        }
+    }
+}
+
+impl Executor for Differential {
+    fn execute(&mut self, code: u32, registers: &mut [i32; 31]) -> Result<(), String> {
+        let inputs = *registers;
+        let mut expected = inputs;
+        self.reference.execute(code, &mut expected)?;
+        // A failed step leaves the registers as they were, there's nothing to undo it with
+        if let Err(e) = self.native.execute(code, registers) {
+            *registers = inputs;
+            return Err(e);
+        }
+        if *registers != expected {
+            let e = divergence(code, &inputs, registers, &expected);
+            *registers = inputs;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+/// Describes the instruction, the registers it read and every register that came out different.
+fn divergence(code: u32, inputs: &[i32; 31], native: &[i32; 31], expected: &[i32; 31]) -> String {
+    let register = |registers: &[i32; 31], x: usize| match x {
+        0 => 0,
+        x => registers[x - 1],
+    };
+    let mut message = format!("Hardware and software disagree on {code:#010x}");
+    if let Ok(instruction) = Instruction::decode(code) {
+        let (rs1, rs2) = (
+            instruction.rs1.to_code() as usize,
+            instruction.rs2.to_code() as usize,
+        );
+        let read = match instruction.instruction.info().format {
+            R => vec![rs1, rs2],
+            U => vec![],
+            _ => vec![rs1],
+        };
+        let read: Vec<String> = read
+            .into_iter()
+            .map(|x| format!("x{x}={}", register(inputs, x)))
+            .collect();
+        message = format!(
+            "Hardware and software disagree on '{instruction}' ({code:#010x}) with {}",
+            if read.is_empty() {
+                "no inputs".to_string()
+            } else {
+                read.join(", ")
+            }
+        );
+    }
+    let differences: Vec<String> = (1..=31)
+        .filter(|&x| register(native, x) != register(expected, x))
+        .map(|x| {
+            format!(
+                "x{x} is {} on the hardware and {} in software",
+                register(native, x),
+                register(expected, x)
+            )
+        })
+        .collect();
+    format!("{message}: {}", differences.join(", "))
+}
+
+/// Writes a [`Record`] line to `out` for every instruction `native` runs.
+#[derive(Debug)]
+pub struct Recorder<W> {
+    native: Box<dyn Executor>,
+    out: W,
+}
+
+impl<W: Write + Debug> Recorder<W> {
+    pub fn new(native: Box<dyn Executor>, out: W) -> Self {
+        Self { native, out }
+    }
+}
+
+impl<W: Write + Debug + Send> Executor for Recorder<W> {
+    fn execute(&mut self, code: u32, registers: &mut [i32; 31]) -> Result<(), String> {
+        let before = *registers;
+        self.native.execute(code, registers)?;
+        let record = Record {
+            code,
+            before,
+            after: *registers,
+        };
+        writeln!(self.out, "{record}").map_err(|e| format!("Can't write the recording: {e}"))
+    }
+}
+
+/// Plays back a recording in place of the hardware, the program has to send the same
+/// instructions with the same registers as when it was recorded.
+#[derive(Debug)]
+pub struct Replay {
+    records: IntoIter<Record>,
+}
+
+impl Replay {
+    pub fn parse(recording: &str) -> Result<Self, String> {
+        let records = recording
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                let record = line.trim().strip_prefix("record ")?;
+                Some(Record::parse(record).map_err(|e| format!("Line {}: {e}", i + 1)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            records: records.into_iter(),
+        })
+    }
+}
+
+impl Executor for Replay {
+    fn execute(&mut self, code: u32, registers: &mut [i32; 31]) -> Result<(), String> {
+        let Some(record) = self.records.next() else {
+            return Err(format!(
+                "The recording has no more instructions, {code:#010x} wasn't recorded"
+            ));
+        };
+        if record.code != code {
+            return Err(format!(
+                "The recording has {:#010x} where the program runs {code:#010x}",
+                record.code
+            ));
+        }
+        if record.before != *registers {
+            return Err(format!(
+                "The registers for {code:#010x} aren't the ones it was recorded with"
+            ));
+        }
+        *registers = record.after;
+        Ok(())
+    }
+}
+
+/// One instruction run by the hardware.
+#[derive(Debug)]
+struct Record {
+    code: u32,
+    before: [i32; 31],
+    after: [i32; 31],
+}
+
+impl Record {
+    /// Parses a line without its `record` prefix.
+    fn parse(record: &str) -> Result<Self, String> {
+        let (code, registers) = record
+            .split_once(' ')
+            .ok_or("Expected an instruction followed by registers")?;
+        let code = code
+            .strip_prefix("0x")
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| format!("'{code}' is not a 0x prefixed instruction word"))?;
+        let (before, after) = registers
+            .split_once("->")
+            .ok_or("Expected '->' between the registers before and after")?;
+        Ok(Self {
+            code,
+            before: parse_registers(before)?,
+            after: parse_registers(after)?,
+        })
+    }
+}
+
+fn parse_registers(registers: &str) -> Result<[i32; 31], String> {
+    let values = registers
+        .split(',')
+        .map(|value| {
+            value
+                .trim()
+                .parse::<i32>()
+                .map_err(|_| format!("'{}' is not a register value", value.trim()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let count = values.len();
+    values
+        .try_into()
+        .map_err(|_| format!("Expected 31 registers, x1 to x31, but got {count}"))
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |registers: &[i32; 31]| {
+            registers
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        write!(
+            f,
+            "record {:#010x} {} -> {}",
+            self.code,
+            join(&self.before),
+            join(&self.after)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Differential, Record, Replay};
+    use crate::assembly::executor::Software;
+    use crate::assembly::{Config, Executor, Interpreter};
+
+    const PROGRAM: [&str; 3] = ["addi t0, zero, 3", "addi t1, zero, 7", "add t2, t0, t1"];
+
+    /// What the program sends to the CPU, with the software's results unless `wrong` replaces
+    /// the value of x7 in the last one.
+    fn recording(wrong: Option<i32>) -> String {
+        let mut interpreter = Interpreter::new(PROGRAM.map(String::from).to_vec()).unwrap();
+        let mut recording = String::from("I (312) boot: ESP-IDF v5.1\n");
+        for code in [0x00300293, 0x00700313, 0x006283b3] {
+            let before = interpreter.registers;
+            let mut after = before;
+            Software.execute(code, &mut after).unwrap();
+            if code == 0x006283b3
+                && let Some(wrong) = wrong
+            {
+                after[6] = wrong;
+            }
+            recording += &format!(
+                "{}\n",
+                Record {
+                    code,
+                    before,
+                    after
+                }
+            );
+            interpreter.step().unwrap();
+        }
+        recording
+    }
+
+    fn replaying(recording: &str) -> Interpreter {
+        let replay = Replay::parse(recording).unwrap();
+        Interpreter::with_executor(
+            PROGRAM.map(String::from).to_vec(),
+            Config::default(),
+            Box::new(Differential::new(Box::new(replay))),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn matching_recording() {
+        let mut interpreter = replaying(&recording(None));
+        while interpreter.step().unwrap().is_some() {}
+        assert_eq!(interpreter.registers[4..7], [3, 7, 10]);
+    }
+
+    #[test]
+    fn diverging_recording() {
+        let mut interpreter = replaying(&recording(Some(99)));
+        interpreter.step().unwrap();
+        interpreter.step().unwrap();
+        let before = interpreter.registers;
+        let e = interpreter.step().unwrap_err();
+        assert_eq!(
+            e,
+            "Hardware and software disagree on 'add x7, x5, x6' (0x006283b3) with x5=3, x6=7: \
+             x7 is 99 on the hardware and 10 in software"
+        );
+        // Neither side's result is kept
+        assert_eq!(interpreter.registers, before);
+        assert_eq!(interpreter.source_line(), Some(3));
+    }
+
+    #[test]
+    fn recording_runs_out() {
+        let recording = recording(None);
+        let first: String = recording
+            .lines()
+            .take(2)
+            .map(|line| format!("{line}\n"))
+            .collect();
+        let mut interpreter = replaying(&first);
+        interpreter.step().unwrap();
+        let e = interpreter.step().unwrap_err();
+        assert!(e.contains("no more instructions"), "{e}");
+    }
+
+    #[test]
+    fn malformed_lines() {
+        let registers = vec!["0"; 31].join(",");
+        for (line, error) in [
+            (
+                "record 0x00300293",
+                "Expected an instruction followed by registers",
+            ),
+            (
+                "record 300293 0 -> 0",
+                "'300293' is not a 0x prefixed instruction word",
+            ),
+            (
+                &format!("record 0x00300293 {registers}"),
+                "Expected '->' between the registers before and after",
+            ),
+            (
+                &format!("record 0x00300293 {registers} -> 1,2"),
+                "Expected 31 registers, x1 to x31, but got 2",
+            ),
+            (
+                &format!(
+                    "record 0x00300293 {registers} -> {},x",
+                    vec!["0"; 30].join(",")
+                ),
+                "'x' is not a register value",
+            ),
+        ] {
+            let e = Replay::parse(&format!("boot\n{line}\n")).unwrap_err();
+            assert_eq!(e, format!("Line 2: {error}"), "{line}");
+        }
+    }
+}
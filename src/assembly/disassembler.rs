@@ -0,0 +1,201 @@
+//! Turns machine code back into instructions and assembly text, the opposite of
+//! `Instruction::encode`.
+
+use std::fmt;
+
+use crate::assembly::InstructionFormat::*;
+use crate::assembly::SupportedInstruction::*;
+use crate::assembly::{Instruction, InstructionInfo, Register, SupportedInstruction};
+
+impl Instruction {
+    /// Decodes a 32-bit instruction word, or explains why it isn't an instruction we know.
+    pub(super) fn decode(word: u32) -> Result<Instruction, String> {
+        let (opcode, funct3, funct7) = (word & 0x7F, (word >> 12) & 0x7, word >> 25);
+        let supported = SupportedInstruction::ALL
+            .into_iter()
+            .find(|supported| {
+                let info = supported.info();
+                let same_funct3 = funct3 == info.funct3 as u32;
+                opcode == info.opcode as u32
+                    && match info.format {
+                        R => same_funct3 && funct7 == info.funct7 as u32,
+                        I if supported.is_shift_immediate() => {
+                            same_funct3 && funct7 == info.funct7 as u32
+                        }
+                        I | S | SB => same_funct3,
+                        U | UJ => true,
+                    }
+            })
+            .ok_or_else(|| format!("unknown instruction {word:#010x}"))?;
+
+        let register = |shift: u32| Register::ALL[((word >> shift) & 0x1F) as usize];
+        let (rd, rs1, rs2) = (register(7), register(15), register(20));
+        let signed = word as i32;
+        let InstructionInfo { format, .. } = supported.info();
+        let (rd, rs1, rs2, imm) = match format {
+            R => (rd, rs1, rs2, 0),
+            I if supported.is_shift_immediate() => (rd, rs1, Register::X0, rs2.to_code()),
+            I => (rd, rs1, Register::X0, signed >> 20),
+            S => (
+                Register::X0,
+                rs1,
+                rs2,
+                (signed >> 25) << 5 | ((word >> 7) & 0x1F) as i32,
+            ),
+            SB => {
+                // imm[12|10:5] rs2 rs1 funct3 imm[4:1|11] opcode
+                let imm = (signed >> 31) << 12
+                    | (((word >> 7) & 0x1) << 11) as i32
+                    | (((word >> 25) & 0x3F) << 5) as i32
+                    | (((word >> 8) & 0xF) << 1) as i32;
+                (Register::X0, rs1, rs2, imm)
+            }
+            U => (rd, Register::X0, Register::X0, (word >> 12) as i32),
+            UJ => {
+                // imm[20|10:1|11|19:12] rd opcode
+                let imm = (signed >> 31) << 20
+                    | (((word >> 12) & 0xFF) << 12) as i32
+                    | (((word >> 20) & 0x1) << 11) as i32
+                    | (((word >> 21) & 0x3FF) << 1) as i32;
+                (rd, Register::X0, Register::X0, imm)
+            }
+        };
+
+        Ok(Instruction {
+            instruction: supported,
+            rd,
+            rs1,
+            rs2,
+            imm,
+        })
+    }
+}
+
+/// Assembly text for a 32-bit instruction word, `unknown instruction 0x...` when it isn't one.
+pub fn disassemble(word: u32) -> String {
+    match Instruction::decode(word) {
+        Ok(instruction) => instruction.to_string(),
+        Err(e) => e,
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "x{}", self.to_code())
+    }
+}
+
+/// Canonical assembly text, which assembles back to the same instruction. Except with the checked
+/// backend for words using registers the firmware needs, like `add x1, x2, x3`, which it refuses.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Instruction {
+            instruction,
+            rd,
+            rs1,
+            rs2,
+            imm,
+        } = self;
+        let mnemonic = instruction.mnemonic();
+        match (instruction.info().format, instruction) {
+            (R, _) => write!(f, "{mnemonic} {rd}, {rs1}, {rs2}"),
+            (I, Jalr | Lb | Lh | Lw | Lbu | Lhu) => write!(f, "{mnemonic} {rd}, {imm}({rs1})"),
+            (I, _) => write!(f, "{mnemonic} {rd}, {rs1}, {imm}"),
+            (S, _) => write!(f, "{mnemonic} {rs2}, {imm}({rs1})"),
+            (SB, _) => write!(f, "{mnemonic} {rs1}, {rs2}, {imm}"),
+            (U, _) => write!(f, "{mnemonic} {rd}, {imm:#x}"),
+            (UJ, _) => write!(f, "{mnemonic} {rd}, {imm}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembly::parser::parse_line;
+    use crate::assembly::{Backend, Instruction, InstructionFormat, Labels, SupportedInstruction};
+
+    /// Decodes `word`, encodes it again, prints it and assembles the text, which all have to give
+    /// `word` back.
+    fn round_trip(word: u32) {
+        let decoded = Instruction::decode(word).unwrap();
+        assert_eq!(decoded.encode(), Ok(word), "{decoded}");
+        let text = decoded.to_string();
+        let line = parse_line(&text).unwrap_or_else(|e| panic!("{text}: {e}"));
+        let built = Instruction::build(&line, 0, &Labels::new(), Backend::Software)
+            .unwrap_or_else(|e| panic!("{text}: {e}"));
+        assert_eq!(built.encode(), Ok(word), "{text}");
+    }
+
+    /// Random words for every instruction in `format`, with its opcode and functs in place.
+    fn words(format: InstructionFormat) -> Vec<u32> {
+        let mut random = 0x2545_f491_u32;
+        let mut words = Vec::new();
+        for supported in SupportedInstruction::ALL {
+            let info = supported.info();
+            if info.format != format {
+                continue;
+            }
+            for _ in 0..2000 {
+                // xorshift
+                random ^= random << 13;
+                random ^= random >> 17;
+                random ^= random << 5;
+                let mut word = random & !0x7F | info.opcode as u32;
+                if !matches!(format, InstructionFormat::U | InstructionFormat::UJ) {
+                    word = word & !(0x7 << 12) | (info.funct3 as u32) << 12;
+                }
+                if format == InstructionFormat::R || supported.is_shift_immediate() {
+                    word = word & 0x01FF_FFFF | (info.funct7 as u32) << 25;
+                }
+                words.push(word);
+            }
+        }
+        words
+    }
+
+    #[test]
+    fn round_trips_r() {
+        words(InstructionFormat::R).into_iter().for_each(round_trip);
+    }
+
+    #[test]
+    fn round_trips_i() {
+        words(InstructionFormat::I).into_iter().for_each(round_trip);
+    }
+
+    #[test]
+    fn round_trips_s() {
+        words(InstructionFormat::S).into_iter().for_each(round_trip);
+    }
+
+    #[test]
+    fn round_trips_u() {
+        words(InstructionFormat::U).into_iter().for_each(round_trip);
+    }
+
+    #[test]
+    fn round_trips_sb() {
+        words(InstructionFormat::SB)
+            .into_iter()
+            .for_each(round_trip);
+    }
+
+    #[test]
+    fn round_trips_uj() {
+        words(InstructionFormat::UJ)
+            .into_iter()
+            .for_each(round_trip);
+    }
+
+    #[test]
+    fn unknown_words() {
+        assert_eq!(
+            super::disassemble(0xFFFF_FFFF),
+            "unknown instruction 0xffffffff"
+        );
+        assert_eq!(
+            super::disassemble(0x0000_0000),
+            "unknown instruction 0x00000000"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Turns instructions into machine code, checking every field fits before it is placed.
+
+use crate::assembly::InstructionFormat::*;
+use crate::assembly::{Instruction, InstructionInfo};
+
+impl Instruction {
+    /// The 32-bit instruction word, or why an operand doesn't fit in its field.
+    pub(super) fn encode(self) -> Result<u32, String> {
+        let InstructionInfo {
+            format,
+            opcode,
+            funct3,
+            funct7,
+        } = self.instruction.info();
+        let (opcode, funct3, funct7) =
+            (opcode as u32, (funct3 as u32) << 12, (funct7 as u32) << 25);
+        let rd = unsigned(self.rd.to_code(), 5, "rd")? << 7;
+        let rs1 = unsigned(self.rs1.to_code(), 5, "rs1")? << 15;
+        let rs2 = unsigned(self.rs2.to_code(), 5, "rs2")? << 20;
+        let imm = self.imm;
+
+        let code = match format {
+            R => funct7 | rs2 | rs1 | funct3 | rd | opcode,
+            I if self.instruction.is_shift_immediate() => {
+                let shamt = unsigned(imm, 5, "shift amount")?;
+                funct7 | shamt << 20 | rs1 | funct3 | rd | opcode
+            }
+            I => signed(imm, 12, "immediate")? << 20 | rs1 | funct3 | rd | opcode,
+            S => {
+                // imm[11:5] rs2 rs1 funct3 imm[4:0] opcode
+                let imm = signed(imm, 12, "offset")?;
+                (imm >> 5) << 25 | rs2 | rs1 | funct3 | (imm & 0x1F) << 7 | opcode
+            }
+            U => unsigned(imm, 20, "upper immediate")? << 12 | rd | opcode,
+            SB => {
+                // imm[12|10:5] rs2 rs1 funct3 imm[4:1|11] opcode
+                let imm = even(signed(imm, 13, "branch offset")?)?;
+                (imm >> 12) << 31
+                    | ((imm >> 5) & 0x3F) << 25
+                    | rs2
+                    | rs1
+                    | funct3
+                    | ((imm >> 1) & 0xF) << 8
+                    | ((imm >> 11) & 0x1) << 7
+                    | opcode
+            }
+            UJ => {
+                // imm[20|10:1|11|19:12] rd opcode
+                let imm = even(signed(imm, 21, "jump offset")?)?;
+                (imm >> 20) << 31
+                    | ((imm >> 1) & 0x3FF) << 21
+                    | ((imm >> 11) & 0x1) << 20
+                    | ((imm >> 12) & 0xFF) << 12
+                    | rd
+                    | opcode
+            }
+        };
+        Ok(code)
+    }
+}
+
+/// `value` as a field of `bits` bits, when it is between 0 and 2^bits - 1.
+fn unsigned(value: i32, bits: u32, field: &str) -> Result<u32, String> {
+    if !(0..1 << bits).contains(&value) {
+        return Err(format!(
+            "The {field} {value} can only be between 0 and {}",
+            (1 << bits) - 1
+        ));
+    }
+    Ok(value as u32)
+}
+
+/// `value` as a two's complement field of `bits` bits, when it is between -2^(bits-1) and
+/// 2^(bits-1) - 1. The bits above the field are cleared.
+fn signed(value: i32, bits: u32, field: &str) -> Result<u32, String> {
+    let limit = 1 << (bits - 1);
+    if !(-limit..limit).contains(&value) {
+        return Err(format!(
+            "The {field} {value} can only be between {} and {}",
+            -limit,
+            limit - 1
+        ));
+    }
+    Ok(value as u32 & ((1 << bits) - 1))
+}
+
+/// Branch and jump offsets drop their lowest bit, so it has to be 0.
+fn even(offset: u32) -> Result<u32, String> {
+    if offset & 1 != 0 {
+        return Err("Branch and jump offsets have to be a multiple of 2 bytes".to_string());
+    }
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembly::parser::parse_line;
+    use crate::assembly::Register::*;
+    use crate::assembly::SupportedInstruction::{self, *};
+    use crate::assembly::{Backend, DiagnosticKind, Instruction, Labels};
+
+    fn encode(instruction: SupportedInstruction, imm: i32) -> Result<u32, String> {
+        Instruction {
+            instruction,
+            rd: X5,
+            rs1: X6,
+            rs2: X7,
+            imm,
+        }
+        .encode()
+    }
+
+    #[test]
+    fn immediates_at_their_limits() {
+        for (instruction, min, max) in [
+            (AddI, -2048, 2047),
+            (SllI, 0, 31),
+            (Sw, -2048, 2047),
+            (Lui, 0, 0xFFFFF),
+            (Beq, -4096, 4094),
+            (Jal, -(1 << 20), (1 << 20) - 2),
+        ] {
+            assert!(encode(instruction, min).is_ok(), "{instruction:?} {min}");
+            assert!(encode(instruction, max).is_ok(), "{instruction:?} {max}");
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_immediates() {
+        for (instruction, imm) in [
+            (AddI, 2048),
+            (AddI, -2049),
+            (Lw, 4096),
+            (SllI, 32),
+            (SraI, -1),
+            (Sw, 2048),
+            (Sb, -2049),
+            (Lui, 0x100000),
+            (AuiPc, -1),
+            (Beq, 4096),
+            (Bne, -4098),
+            (Jal, 1 << 20),
+            (Jal, -(1 << 20) - 2),
+        ] {
+            let e = encode(instruction, imm).unwrap_err();
+            assert!(e.contains(&imm.to_string()), "{instruction:?} {imm}: {e}");
+        }
+    }
+
+    #[test]
+    fn rejects_odd_branch_offsets() {
+        for (instruction, imm) in [(Beq, 3), (Bgeu, -1), (Jal, 5), (Jal, -7)] {
+            assert_eq!(
+                encode(instruction, imm),
+                Err("Branch and jump offsets have to be a multiple of 2 bytes".to_string()),
+                "{instruction:?} {imm}"
+            );
+        }
+    }
+
+    #[test]
+    fn assembler_rejects_what_does_not_fit() {
+        for (text, kind) in [
+            ("addi t0, t0, 2048", DiagnosticKind::OutOfRange),
+            ("slli t0, t0, 32", DiagnosticKind::OutOfRange),
+            ("lui t0, 0x100000", DiagnosticKind::OutOfRange),
+            ("beq t0, t1, 3", DiagnosticKind::OutOfRange),
+            ("jal x0, 1048576", DiagnosticKind::OutOfRange),
+        ] {
+            let line = parse_line(text).unwrap();
+            let e = Instruction::build(&line, 0, &Labels::new(), Backend::Software).unwrap_err();
+            assert_eq!(e.kind, kind, "{text}: {e}");
+        }
+    }
+}
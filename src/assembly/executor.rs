@@ -0,0 +1,187 @@
+//! What runs the instructions `Interpreter` sends to the CPU: the arithmetic and logic
+//! instructions and `lui`. Everything else is always done by the interpreter itself.
+
+#[cfg(target_arch = "riscv32")]
+use std::arch::asm;
+use std::fmt::Debug;
+#[cfg(target_arch = "riscv32")]
+use std::io;
+
+use crate::assembly::Register::*;
+use crate::assembly::SupportedInstruction::*;
+#[cfg(target_arch = "riscv32")]
+use crate::assembly::{Differential, Recorder};
+use crate::assembly::{Instruction, Register};
+
+/// `Send` so sessions can be stepped from whichever thread answers the request.
+pub trait Executor: Debug + Send {
+    /// Executes the instruction `code` on `registers`, which holds x1 to x31.
+    fn execute(&mut self, code: u32, registers: &mut [i32; 31]) -> Result<(), String>;
+}
+
+/// Which [`Executor`] a session runs on, the real CPU when we are on it.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Backend {
+    /// The CPU the interpreter is running on, only available on RISC-V.
+    #[cfg(target_arch = "riscv32")]
+    #[default]
+    Hardware,
+    #[cfg_attr(not(target_arch = "riscv32"), default)]
+    Software,
+    /// The CPU, checked against the software after every instruction. What the CPU did is
+    /// recorded on stdout, to be replayed with [`crate::assembly::Replay`].
+    #[cfg(target_arch = "riscv32")]
+    Checked,
+}
+
+impl Backend {
+    /// Whether every instruction has to go to the CPU, which can't use the registers the
+    /// firmware needs. The hardware backend runs instructions using them in software instead.
+    pub(super) fn needs_cpu(self) -> bool {
+        match self {
+            #[cfg(target_arch = "riscv32")]
+            Backend::Checked => true,
+            _ => false,
+        }
+    }
+
+    pub fn executor(self) -> Box<dyn Executor> {
+        match self {
+            #[cfg(target_arch = "riscv32")]
+            Backend::Hardware => Box::new(Hardware),
+            Backend::Software => Box::new(Software),
+            #[cfg(target_arch = "riscv32")]
+            Backend::Checked => Box::new(Differential::new(Box::new(Recorder::new(
+                Box::new(Hardware),
+                io::stdout(),
+            )))),
+        }
+    }
+}
+
+/// Jumps into the instruction with inline assembly, so it runs on the actual CPU.
+#[cfg(target_arch = "riscv32")]
+#[derive(Debug)]
+pub struct Hardware;
+
+#[cfg(target_arch = "riscv32")]
+impl Executor for Hardware {
+    fn execute(&mut self, code: u32, registers: &mut [i32; 31]) -> Result<(), String> {
+        #[allow(unused_mut)]
+        #[allow(unused_variables)]
+        #[allow(unused_assignments)]
+        let [mut x1, mut x2, mut x3, mut x4, mut x5, mut x6, mut x7, mut x8, mut x9, mut x10, mut x11, mut x12, mut x13, mut x14, mut x15, mut x16, mut x17, mut x18, mut x19, mut x20, mut x21, mut x22, mut x23, mut x24, mut x25, mut x26, mut x27, mut x28, mut x29, mut x30, mut x31] =
+            *registers;
+
+        let instruction: [u32; 2] = [
+            code,
+            0b00000000000000001000000001100111, //jalr x0, 0(x1)
+        ];
+
+        let instruction_pointer: *const [u32; 2] = &instruction;
+
+        // Oh hmm DRAM vs IRAM https://www.espressif.com/sites/default/files/documentation/esp32-c3_technical_reference_manual_en.pdf#sysmem
+        let diff_data_and_instruction_bus: i32 = 0x70_0000;
+
+        unsafe {
+            asm!(
+            "addi sp, sp, -4", // Add space on stack
+            "sw x1, 0(sp)", // Store return address on stack
+            "add x30, x30, x29", //Up the data bus known address to get the shadowed
+                                 //instruction bus address.
+            "jalr x1, 0(x30)",
+            "lw x1, 0(sp)", // Restore return address
+            "addi sp, sp, 4", //pop!
+
+            // x1, x29 and x30 are needed to get to the instruction and back, x2-x4, x8 and x9
+            // can't be operands at all. The interpreter runs instructions using them in software,
+            // see `Register::hardware_reserved`.
+            // Clobbered instead of written back, so the program's own values survive.
+            out("x1") _,
+            inout("x5") x5,
+            inout("x6") x6,
+            inout("x7") x7,
+            inout("x10") x10,
+            inout("x11") x11,
+            inout("x12") x12,
+            inout("x13") x13,
+            inout("x14") x14,
+            inout("x15") x15,
+            inout("x16") x16,
+            inout("x17") x17,
+            inout("x18") x18,
+            inout("x19") x19,
+            inout("x20") x20,
+            inout("x21") x21,
+            inout("x22") x22,
+            inout("x23") x23,
+            inout("x24") x24,
+            inout("x25") x25,
+            inout("x26") x26,
+            inout("x27") x27,
+            inout("x28") x28,
+            inout("x29") diff_data_and_instruction_bus => _,
+            inout("x30") instruction_pointer as i32 => _,
+            inout("x31") x31,
+            );
+        }
+
+        *registers = [
+            x1, x2, x3, x4, x5, x6, x7, x8, x9, x10, x11, x12, x13, x14, x15, x16, x17, x18, x19,
+            x20, x21, x22, x23, x24, x25, x26, x27, x28, x29, x30, x31,
+        ];
+        Ok(())
+    }
+}
+
+/// Computes the result in plain Rust, runs anywhere.
+#[derive(Debug)]
+pub struct Software;
+
+impl Executor for Software {
+    fn execute(&mut self, code: u32, registers: &mut [i32; 31]) -> Result<(), String> {
+        let instruction = Instruction::decode(code)?;
+        let read = |register: Register| match register {
+            X0 => 0,
+            r => registers[r.to_code() as usize - 1],
+        };
+        let (rs1, rs2, imm) = (
+            read(instruction.rs1),
+            read(instruction.rs2),
+            instruction.imm,
+        );
+        // Shifts only use the lowest 5 bits of the amount
+        let result = match instruction.instruction {
+            Add => rs1.wrapping_add(rs2),
+            Sub => rs1.wrapping_sub(rs2),
+            And => rs1 & rs2,
+            Or => rs1 | rs2,
+            Xor => rs1 ^ rs2,
+            Sll => rs1 << (rs2 & 0x1F),
+            Srl => ((rs1 as u32) >> (rs2 & 0x1F)) as i32,
+            Sra => rs1 >> (rs2 & 0x1F),
+            Slt => (rs1 < rs2) as i32,
+            Sltu => ((rs1 as u32) < (rs2 as u32)) as i32,
+            AddI => rs1.wrapping_add(imm),
+            AndI => rs1 & imm,
+            OrI => rs1 | imm,
+            XorI => rs1 ^ imm,
+            SllI => rs1 << (imm & 0x1F),
+            SrlI => ((rs1 as u32) >> (imm & 0x1F)) as i32,
+            SraI => rs1 >> (imm & 0x1F),
+            SltI => (rs1 < imm) as i32,
+            // The immediate is sign extended first and then compared unsigned
+            SltIU => ((rs1 as u32) < (imm as u32)) as i32,
+            Lui => imm << 12,
+            _ => {
+                return Err(format!(
+                    "'{instruction}' is run by the interpreter, not sent to the CPU"
+                ))
+            }
+        };
+        if instruction.rd != X0 {
+            registers[instruction.rd.to_code() as usize - 1] = result;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+//! Going back in time: every step leaves behind what it overwrote, so it can be put back.
+
+use crate::assembly::SupportedInstruction::*;
+use crate::assembly::{Interpreter, Register};
+
+/// What one step changed, as it was before the step.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Undo {
+    line: usize,
+    /// An instruction writes at most its `rd`.
+    register: (Register, i32),
+    /// Where a store wrote and the bytes that were there.
+    memory: Option<(usize, [u8; 4], usize)>,
+}
+
+impl Interpreter {
+    /// What running the next instruction is going to overwrite.
+    pub(crate) fn undo(&self) -> Undo {
+        let instruction = self.program[self.line];
+        let memory = match instruction.instruction {
+            Sb | Sh | Sw => self.access_range(&instruction).ok().map(|range| {
+                let mut bytes = [0; 4];
+                bytes[..range.len()].copy_from_slice(&self.memory[range.clone()]);
+                (range.start, bytes, range.len())
+            }),
+            _ => None,
+        };
+        Undo {
+            line: self.line,
+            register: (instruction.rd, self.registers(&instruction.rd)),
+            memory,
+        }
+    }
+
+    /// Keeps the last `Config::history` steps.
+    pub(crate) fn remember(&mut self, undo: Undo) {
+        if self.history_size == 0 {
+            return;
+        }
+        if self.history.len() == self.history_size {
+            self.history.pop_front();
+        }
+        self.history.push_back(undo);
+    }
+
+    /// Undoes the last step, false when there is nothing left to undo.
+    pub fn step_back(&mut self) -> bool {
+        let Some(undo) = self.history.pop_back() else {
+            return false;
+        };
+        self.line = undo.line;
+        let (register, value) = undo.register;
+        self.set_register(&register, value);
+        if let Some((start, bytes, width)) = undo.memory {
+            self.memory[start..start + width].copy_from_slice(&bytes[..width]);
+        }
+        if let Some(trace) = &mut self.trace
+            && trace
+                .entries
+                .last()
+                .is_some_and(|entry| entry.step == self.steps)
+        {
+            trace.entries.pop();
+        }
+        self.steps -= 1;
+        true
+    }
+
+    /// Steps back until only `step` instructions have run, 0 is the start of the program.
+    pub fn rewind_to(&mut self, step: u64) -> Result<(), String> {
+        if step > self.steps {
+            return Err(format!(
+                "Step {step} hasn't run yet, the program is at step {}",
+                self.steps
+            ));
+        }
+        let oldest = self.steps - self.history.len() as u64;
+        if step < oldest {
+            return Err(format!(
+                "Only the last {} steps are remembered, can't go back further than step {oldest}",
+                self.history.len()
+            ));
+        }
+        while self.steps > step {
+            self.step_back();
+        }
+        Ok(())
+    }
+
+    /// How many steps [`Interpreter::step_back`] can undo.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembly::{Config, Interpreter};
+
+    const PROGRAM: [&str; 5] = [
+        "addi t0, zero, 5",
+        "sw t0, -4(sp)",
+        "addi t0, t0, 1",
+        "sb t0, -3(sp)",
+        "lw t1, -4(sp)",
+    ];
+
+    fn interpreter(history: usize) -> Interpreter {
+        let program = PROGRAM.iter().map(|line| line.to_string()).collect();
+        let config = Config {
+            history,
+            ..Config::default()
+        };
+        Interpreter::with_config(program, config).unwrap()
+    }
+
+    type State = ([i32; 31], Vec<u8>, u64, Option<usize>);
+
+    fn state(interpreter: &Interpreter) -> State {
+        (
+            interpreter.registers,
+            interpreter.memory().to_vec(),
+            interpreter.steps(),
+            interpreter.source_line(),
+        )
+    }
+
+    /// The state before every step and at the end.
+    fn run(interpreter: &mut Interpreter) -> Vec<State> {
+        let mut states = vec![state(interpreter)];
+        while interpreter.step().unwrap().is_some() {
+            states.push(state(interpreter));
+        }
+        states
+    }
+
+    #[test]
+    fn step_back_undoes_each_step() {
+        let mut interpreter = interpreter(10);
+        let states = run(&mut interpreter);
+        assert_eq!(states.len(), PROGRAM.len() + 1);
+        assert_ne!(states[1].1, states[2].1, "sw didn't write memory");
+        for expected in states.iter().rev().skip(1) {
+            assert!(interpreter.step_back());
+            assert_eq!(&state(&interpreter), expected);
+        }
+        assert!(!interpreter.step_back());
+        assert_eq!(interpreter.history_len(), 0);
+    }
+
+    #[test]
+    fn stepping_again_after_going_back() {
+        let mut interpreter = interpreter(10);
+        let states = run(&mut interpreter);
+        interpreter.rewind_to(1).unwrap();
+        assert_eq!(state(&interpreter), states[1]);
+        assert_eq!(run(&mut interpreter), states[1..]);
+    }
+
+    #[test]
+    fn rewind_to() {
+        let mut interpreter = interpreter(10);
+        let states = run(&mut interpreter);
+        assert!(interpreter.rewind_to(6).is_err());
+        interpreter.rewind_to(3).unwrap();
+        assert_eq!(state(&interpreter), states[3]);
+        interpreter.rewind_to(0).unwrap();
+        assert_eq!(state(&interpreter), states[0]);
+        assert!(interpreter.rewind_to(1).is_err());
+    }
+
+    #[test]
+    fn history_is_limited() {
+        let mut interpreter = interpreter(2);
+        let states = run(&mut interpreter);
+        assert_eq!(interpreter.history_len(), 2);
+        assert!(interpreter.rewind_to(2).is_err());
+        // A failed rewind doesn't go back part of the way
+        assert_eq!(state(&interpreter), states[5]);
+        interpreter.rewind_to(3).unwrap();
+        assert_eq!(state(&interpreter), states[3]);
+        assert!(!interpreter.step_back());
+
+        let mut interpreter = self::interpreter(0);
+        run(&mut interpreter);
+        assert!(!interpreter.step_back());
+    }
+}
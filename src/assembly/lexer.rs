@@ -0,0 +1,252 @@
+//! Splits a line of assembly into tokens, remembering where in the line each one came from.
+
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::assembly::{Diagnostic, DiagnosticKind, Register};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum TokenKind {
+    /// Mnemonics and labels
+    Identifier,
+    Register(Register),
+    Immediate(i32),
+    Comma,
+    Colon,
+    LeftParen,
+    RightParen,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Token {
+    pub kind: TokenKind,
+    /// Byte range of the token in the line.
+    pub span: Range<usize>,
+}
+
+/// Tokenizes a single line, comments starting with `#` or `//` are dropped.
+pub(super) fn tokenize(line: &str) -> Result<Vec<Token>, Diagnostic> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, c)| c);
+        let kind = match c {
+            c if c.is_whitespace() => continue,
+            '#' => break,
+            '/' if next == Some('/') => break,
+            ',' => TokenKind::Comma,
+            ':' => TokenKind::Colon,
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            c if c.is_ascii_digit()
+                || (matches!(c, '-' | '+') && next.is_some_and(|n| n.is_ascii_digit())) =>
+            {
+                let end = take_while(&mut chars, |c| c.is_ascii_alphanumeric() || c == '_', line);
+                TokenKind::Immediate(parse_immediate(&line[start..end], start..end)?)
+            }
+            c if is_identifier_start(c) => {
+                let end = take_while(&mut chars, is_identifier_char, line);
+                match Register::from_str(&line[start..end]) {
+                    Ok(register) => TokenKind::Register(register),
+                    Err(_) => TokenKind::Identifier,
+                }
+            }
+            c => {
+                return Err(Diagnostic::new(
+                    DiagnosticKind::Syntax,
+                    start..start + c.len_utf8(),
+                    format!("Unexpected '{c}'"),
+                ))
+            }
+        };
+        let end = chars.peek().map_or(line.len(), |&(i, _)| i);
+        tokens.push(Token {
+            kind,
+            span: start..end,
+        });
+    }
+    Ok(tokens)
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.'
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+/// Consumes characters while `accept` holds and returns the byte index just past them.
+fn take_while(
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+    accept: impl Fn(char) -> bool,
+    line: &str,
+) -> usize {
+    while let Some(&(i, c)) = chars.peek() {
+        if !accept(c) {
+            return i;
+        }
+        chars.next();
+    }
+    line.len()
+}
+
+/// Parses decimal or `0x` prefixed hexadecimal immediates.
+fn parse_immediate(imm: &str, span: Range<usize>) -> Result<i32, Diagnostic> {
+    let (negative, digits) = match imm.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, imm.strip_prefix('+').unwrap_or(imm)),
+    };
+    let parsed = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse::<i64>(),
+    };
+    match parsed {
+        Ok(n) if negative => i32::try_from(-n),
+        // Anything up to 0xFFFFFFFF is fine, it's the bit pattern that counts
+        Ok(n) => i32::try_from(n).or_else(|_| u32::try_from(n).map(|n| n as i32)),
+        Err(_) => {
+            return Err(Diagnostic::new(
+                DiagnosticKind::Syntax,
+                span,
+                format!("Can't parse '{imm}' as an immediate"),
+            )
+            .with_hint("write numbers in decimal or as 0x prefixed hexadecimal"))
+        }
+    }
+    .map_err(|_| {
+        Diagnostic::new(
+            DiagnosticKind::OutOfRange,
+            span,
+            format!("'{imm}' doesn't fit in 32 bits"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(line: &str) -> Vec<TokenKind> {
+        tokenize(line)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    fn error(line: &str) -> Diagnostic {
+        tokenize(line).unwrap_err()
+    }
+
+    #[test]
+    fn tokens_and_spans() {
+        let tokens = tokenize("loop: lw t0, -8(sp) # the end").unwrap();
+        let spans: Vec<Range<usize>> = tokens.iter().map(|token| token.span.clone()).collect();
+        assert_eq!(
+            spans,
+            [
+                0..4,
+                4..5,
+                6..8,
+                9..11,
+                11..12,
+                13..15,
+                15..16,
+                16..18,
+                18..19
+            ]
+        );
+        assert_eq!(
+            tokens.iter().map(|token| token.kind).collect::<Vec<_>>(),
+            [
+                TokenKind::Identifier,
+                TokenKind::Colon,
+                TokenKind::Identifier,
+                TokenKind::Register(Register::X5),
+                TokenKind::Comma,
+                TokenKind::Immediate(-8),
+                TokenKind::LeftParen,
+                TokenKind::Register(Register::X2),
+                TokenKind::RightParen,
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_and_blanks() {
+        assert!(kinds("").is_empty());
+        assert!(kinds("   \t").is_empty());
+        assert!(kinds("# all comment").is_empty());
+        assert_eq!(kinds("nop // also a comment"), [TokenKind::Identifier]);
+        // A single slash isn't a comment
+        assert_eq!(error("nop / 2").columns, 4..5);
+    }
+
+    #[test]
+    fn immediates() {
+        for (text, value) in [
+            ("0", 0),
+            ("42", 42),
+            ("+42", 42),
+            ("-42", -42),
+            ("0x10", 16),
+            ("0XfF", 255),
+            ("-0x10", -16),
+            ("2147483647", i32::MAX),
+            ("-2147483648", i32::MIN),
+            ("0xFFFFFFFF", -1),
+            ("4294967295", -1),
+            ("0x80000000", i32::MIN),
+        ] {
+            assert_eq!(kinds(text), [TokenKind::Immediate(value)], "{text}");
+        }
+    }
+
+    #[test]
+    fn bad_immediates() {
+        for (text, kind) in [
+            ("0x100000000", DiagnosticKind::OutOfRange),
+            ("4294967296", DiagnosticKind::OutOfRange),
+            ("-2147483649", DiagnosticKind::OutOfRange),
+            ("12abc", DiagnosticKind::Syntax),
+            ("0x", DiagnosticKind::Syntax),
+            ("1_000", DiagnosticKind::Syntax),
+        ] {
+            let error = error(&format!("li t0, {text}"));
+            assert_eq!(error.kind, kind, "{text}");
+            assert_eq!(error.columns, 7..7 + text.len(), "{text}");
+        }
+    }
+
+    #[test]
+    fn registers_and_identifiers() {
+        assert_eq!(
+            kinds("x0 zero ra s0 fp x31 t6 x32 .L1 _start"),
+            [
+                TokenKind::Register(Register::X0),
+                TokenKind::Register(Register::X0),
+                TokenKind::Register(Register::X1),
+                TokenKind::Register(Register::X8),
+                TokenKind::Register(Register::X8),
+                TokenKind::Register(Register::X31),
+                TokenKind::Register(Register::X31),
+                TokenKind::Identifier,
+                TokenKind::Identifier,
+                TokenKind::Identifier,
+            ]
+        );
+    }
+
+    #[test]
+    fn unexpected_characters() {
+        let error = error("addi t0, t0, 1;");
+        assert_eq!(error.kind, DiagnosticKind::Syntax);
+        assert_eq!(error.columns, 14..15);
+        // Columns are bytes, not characters
+        assert_eq!(self::error("addi t0, t0, é").columns, 13..15);
+    }
+}
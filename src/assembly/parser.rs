@@ -0,0 +1,292 @@
+//! Grammar for a line of assembly on top of the tokens from the lexer:
+//!
+//! ```text
+//! line    := [label ':'] [mnemonic [operand {[','] operand}]]
+//! operand := register | immediate | label | [immediate] '(' register ')'
+//! ```
+
+use std::iter::Peekable;
+use std::ops::Range;
+use std::vec::IntoIter;
+
+use crate::assembly::lexer::{tokenize, Token, TokenKind};
+use crate::assembly::{Diagnostic, DiagnosticKind, Register};
+
+#[derive(Debug)]
+pub(super) struct Line<'a> {
+    pub label: Option<(&'a str, Range<usize>)>,
+    pub mnemonic: Option<(&'a str, Range<usize>)>,
+    pub operands: Vec<Operand<'a>>,
+}
+
+impl Line<'_> {
+    /// Span of all operands together, or of the mnemonic when there are none.
+    pub fn operands_span(&self) -> Range<usize> {
+        match (self.operands.first(), self.operands.last()) {
+            (Some(first), Some(last)) => first.span.start..last.span.end,
+            _ => self.mnemonic_span(),
+        }
+    }
+
+    pub fn mnemonic_span(&self) -> Range<usize> {
+        self.mnemonic
+            .as_ref()
+            .map_or(0..0, |(_, span)| span.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Operand<'a> {
+    pub kind: OperandKind<'a>,
+    /// Byte range of the operand in the line.
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum OperandKind<'a> {
+    Register(Register),
+    Immediate(i32),
+    Label(&'a str),
+    /// `offset(base)`, like `8(sp)`
+    Memory {
+        offset: i32,
+        base: Register,
+    },
+}
+
+pub(super) fn parse_line(line: &str) -> Result<Line<'_>, Diagnostic> {
+    let mut tokens = tokenize(line)?.into_iter().peekable();
+    let text = |token: &Token| &line[token.span.clone()];
+    let syntax = |span: Range<usize>, message: String| {
+        Diagnostic::new(DiagnosticKind::Syntax, span, message)
+    };
+
+    let mut label = None;
+    let mut mnemonic = None;
+    if let Some(first) = tokens.next() {
+        let is_label = tokens
+            .peek()
+            .is_some_and(|next| next.kind == TokenKind::Colon);
+        match first.kind {
+            TokenKind::Identifier if is_label => {
+                tokens.next();
+                label = Some((text(&first), first.span));
+                if let Some(token) = tokens.next() {
+                    match token.kind {
+                        TokenKind::Identifier => mnemonic = Some((text(&token), token.span)),
+                        _ => {
+                            return Err(syntax(
+                                token.span.clone(),
+                                format!("Expected an instruction but got '{}'", text(&token)),
+                            ))
+                        }
+                    }
+                }
+            }
+            TokenKind::Register(_) if is_label => {
+                return Err(syntax(
+                    first.span.clone(),
+                    format!(
+                        "'{}' is a register and can't be used as a label",
+                        text(&first)
+                    ),
+                ))
+            }
+            TokenKind::Identifier => mnemonic = Some((text(&first), first.span)),
+            _ => {
+                return Err(syntax(
+                    first.span.clone(),
+                    format!(
+                        "Expected an instruction or label but got '{}'",
+                        text(&first)
+                    ),
+                ))
+            }
+        }
+    }
+
+    let mut operands = Vec::new();
+    while tokens.peek().is_some() {
+        if !operands.is_empty() && tokens.peek().is_some_and(|t| t.kind == TokenKind::Comma) {
+            tokens.next();
+        }
+        operands.push(parse_operand(&mut tokens, line)?);
+    }
+
+    Ok(Line {
+        label,
+        mnemonic,
+        operands,
+    })
+}
+
+fn parse_operand<'a>(
+    tokens: &mut Peekable<IntoIter<Token>>,
+    line: &'a str,
+) -> Result<Operand<'a>, Diagnostic> {
+    let Some(token) = tokens.next() else {
+        return Err(Diagnostic::new(
+            DiagnosticKind::Syntax,
+            line.len()..line.len(),
+            "Expected an operand after ','".to_string(),
+        ));
+    };
+    let (kind, end) = match token.kind {
+        TokenKind::Register(register) => (OperandKind::Register(register), token.span.end),
+        TokenKind::Identifier => (
+            OperandKind::Label(&line[token.span.clone()]),
+            token.span.end,
+        ),
+        TokenKind::Immediate(offset)
+            if tokens
+                .peek()
+                .is_some_and(|next| next.kind == TokenKind::LeftParen) =>
+        {
+            tokens.next();
+            parse_memory_base(tokens, line, offset, token.span.start)?
+        }
+        TokenKind::Immediate(imm) => (OperandKind::Immediate(imm), token.span.end),
+        TokenKind::LeftParen => parse_memory_base(tokens, line, 0, token.span.start)?,
+        _ => {
+            return Err(Diagnostic::new(
+                DiagnosticKind::Syntax,
+                token.span.clone(),
+                format!("Expected an operand but got '{}'", &line[token.span]),
+            ))
+        }
+    };
+    Ok(Operand {
+        kind,
+        span: token.span.start..end,
+    })
+}
+
+/// Parses the `register)` part of a memory operand, after the `(`, and returns where it ends.
+fn parse_memory_base<'a>(
+    tokens: &mut Peekable<IntoIter<Token>>,
+    line: &'a str,
+    offset: i32,
+    start: usize,
+) -> Result<(OperandKind<'a>, usize), Diagnostic> {
+    let hint = "memory operands look like 8(sp)";
+    let base = match tokens.next() {
+        Some(Token {
+            kind: TokenKind::Register(base),
+            ..
+        }) => base,
+        Some(token) => {
+            return Err(Diagnostic::new(
+                DiagnosticKind::Syntax,
+                token.span.clone(),
+                format!(
+                    "Expected a register after '(' but got '{}'",
+                    &line[token.span]
+                ),
+            )
+            .with_hint(hint))
+        }
+        None => {
+            return Err(Diagnostic::new(
+                DiagnosticKind::Syntax,
+                start..line.len(),
+                "Expected a register after '('".to_string(),
+            )
+            .with_hint(hint))
+        }
+    };
+    match tokens.next() {
+        Some(Token {
+            kind: TokenKind::RightParen,
+            span,
+        }) => Ok((OperandKind::Memory { offset, base }, span.end)),
+        next => Err(Diagnostic::new(
+            DiagnosticKind::Syntax,
+            start..next.map_or(line.len(), |token| token.span.end),
+            "Expected ')' after the register".to_string(),
+        )
+        .with_hint(hint)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(line: &str) -> Diagnostic {
+        parse_line(line).unwrap_err()
+    }
+
+    #[test]
+    fn label_and_instruction() {
+        let line = parse_line("loop: beq t0, t1, loop").unwrap();
+        assert_eq!(line.label, Some(("loop", 0..4)));
+        assert_eq!(line.mnemonic, Some(("beq", 6..9)));
+        assert_eq!(line.operands.len(), 3);
+        assert!(matches!(
+            line.operands[0].kind,
+            OperandKind::Register(Register::X5)
+        ));
+        assert!(matches!(line.operands[2].kind, OperandKind::Label("loop")));
+        assert_eq!(line.operands[2].span, 18..22);
+        assert_eq!(line.operands_span(), 10..22);
+    }
+
+    #[test]
+    fn only_a_label_or_nothing() {
+        let line = parse_line("end:").unwrap();
+        assert_eq!(line.label, Some(("end", 0..3)));
+        assert!(line.mnemonic.is_none());
+        let line = parse_line("  # nothing here").unwrap();
+        assert!(line.label.is_none() && line.mnemonic.is_none() && line.operands.is_empty());
+        assert_eq!(line.operands_span(), 0..0);
+    }
+
+    #[test]
+    fn commas_are_optional() {
+        let line = parse_line("add t0 t1 t2").unwrap();
+        assert_eq!(line.operands.len(), 3);
+        assert_eq!(parse_line("ecall").unwrap().operands_span(), 0..5);
+    }
+
+    #[test]
+    fn memory_operands() {
+        let line = parse_line("sw t0, -12(sp)").unwrap();
+        assert!(matches!(
+            line.operands[1].kind,
+            OperandKind::Memory {
+                offset: -12,
+                base: Register::X2
+            }
+        ));
+        assert_eq!(line.operands[1].span, 7..14);
+        let line = parse_line("lw t0, (a0)").unwrap();
+        assert!(matches!(
+            line.operands[1].kind,
+            OperandKind::Memory {
+                offset: 0,
+                base: Register::X10
+            }
+        ));
+        assert_eq!(line.operands[1].span, 7..11);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for (line, columns) in [
+            ("x5: addi t0, t0, 1", 0..2),
+            ("loop: 5", 6..7),
+            ("5", 0..1),
+            ("addi t0, t0,", 12..12),
+            ("addi t0, , 1", 9..10),
+            ("lw t0, 4(8)", 9..10),
+            ("lw t0, 4(", 7..9),
+            ("lw t0, 4(sp", 7..11),
+            ("lw t0, 4(sp, 3", 7..12),
+        ] {
+            let error = error(line);
+            assert_eq!(error.kind, DiagnosticKind::Syntax, "{line}");
+            assert_eq!(error.columns, columns, "{line}");
+        }
+        assert!(error("lw t0, (sp").hint.is_some());
+    }
+}
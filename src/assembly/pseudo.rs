@@ -0,0 +1,236 @@
+//! Pseudo-instructions from the textbooks, expanded into the real instructions the CPU runs.
+
+use crate::assembly::parser::{Line, Operand, OperandKind};
+use crate::assembly::Register::*;
+use crate::assembly::SupportedInstruction::{self, *};
+use crate::assembly::{resolve_target, Backend, Diagnostic, DiagnosticKind, Instruction, Labels};
+
+use OperandKind::{Immediate as Imm, Register as Reg};
+
+/// Number of real instructions `line` expands to, needed up front to know where labels point.
+pub(super) fn size(line: &Line) -> usize {
+    let kinds: Vec<OperandKind> = line.operands.iter().map(|o| o.kind).collect();
+    match (mnemonic(line).as_str(), kinds.as_slice()) {
+        ("li", [_, Imm(imm)]) if !(-2048..=2047).contains(imm) => 2,
+        ("la" | "call", _) => 2,
+        _ => 1,
+    }
+}
+
+/// Expands a pseudo-instruction, or builds `line` as a single real instruction when it isn't one.
+/// `index` is the position of the first resulting instruction in the program.
+pub(super) fn expand(
+    line: &Line,
+    index: usize,
+    labels: &Labels,
+    backend: Backend,
+) -> Result<Vec<Instruction>, Diagnostic> {
+    let mnemonic = mnemonic(line);
+    let operands = &line.operands;
+    let kinds: Vec<OperandKind> = operands.iter().map(|o| o.kind).collect();
+    // Every operand of the real instructions points at the source operand it came from, the
+    // ones the pseudo-instruction adds by itself at its mnemonic
+    let from = |i: usize| operands[i].clone();
+    let with = |i: usize, kind| Operand {
+        kind,
+        span: operands[i].span.clone(),
+    };
+    let fixed = |kind| Operand {
+        kind,
+        span: line.mnemonic_span(),
+    };
+    let real: Vec<(SupportedInstruction, Vec<Operand>)> =
+        match (mnemonic.as_str(), kinds.as_slice()) {
+            ("nop", []) => vec![(AddI, vec![fixed(Reg(X0)), fixed(Reg(X0)), fixed(Imm(0))])],
+            ("li", [_, Imm(imm)]) if (-2048..=2047).contains(imm) => {
+                vec![(AddI, vec![from(0), fixed(Reg(X0)), from(1)])]
+            }
+            ("li", [_, Imm(imm)]) => {
+                let (upper, lower) = split_immediate(*imm);
+                vec![
+                    (Lui, vec![from(0), with(1, Imm(upper))]),
+                    (AddI, vec![from(0), from(0), with(1, Imm(lower))]),
+                ]
+            }
+            ("la", [_, target]) => {
+                let target = resolve_target(target, &operands[1].span, index, labels)?;
+                let (upper, lower) = split_immediate(target);
+                vec![
+                    (AuiPc, vec![from(0), with(1, Imm(upper))]),
+                    (AddI, vec![from(0), from(0), with(1, Imm(lower))]),
+                ]
+            }
+            ("call", [target]) => {
+                let target = resolve_target(target, &operands[0].span, index, labels)?;
+                let (upper, lower) = split_immediate(target);
+                vec![
+                    (AuiPc, vec![fixed(Reg(X1)), with(0, Imm(upper))]),
+                    (
+                        Jalr,
+                        vec![fixed(Reg(X1)), fixed(Reg(X1)), with(0, Imm(lower))],
+                    ),
+                ]
+            }
+            ("mv", [_, _]) => vec![(AddI, vec![from(0), from(1), fixed(Imm(0))])],
+            ("not", [_, _]) => vec![(XorI, vec![from(0), from(1), fixed(Imm(-1))])],
+            ("neg", [_, _]) => vec![(Sub, vec![from(0), fixed(Reg(X0)), from(1)])],
+            ("j", [_]) => vec![(Jal, vec![fixed(Reg(X0)), from(0)])],
+            ("ret", []) => vec![(Jalr, vec![fixed(Reg(X0)), fixed(Reg(X1)), fixed(Imm(0))])],
+            ("beqz", [_, _]) => vec![(Beq, vec![from(0), fixed(Reg(X0)), from(1)])],
+            ("bnez", [_, _]) => vec![(Bne, vec![from(0), fixed(Reg(X0)), from(1)])],
+            // Swapping the operands turns these into the real branches
+            ("bgt", [_, _, _]) => vec![(Blt, vec![from(1), from(0), from(2)])],
+            ("ble", [_, _, _]) => vec![(Bge, vec![from(1), from(0), from(2)])],
+            (pseudo, _) if usage(pseudo).is_some() => return Err(wrong_operands(line)),
+            _ => {
+                return Instruction::build(line, index, labels, backend)
+                    .map(|instruction| vec![instruction])
+            }
+        };
+
+    real.into_iter()
+        .enumerate()
+        .map(|(i, (supported, operands))| {
+            let real = Line {
+                label: None,
+                mnemonic: line.mnemonic.clone(),
+                operands,
+            };
+            Instruction::build_as(supported, &real, index + i, labels, backend).map_err(|e| {
+                // The real instruction's operands aren't what the student wrote
+                match e.kind {
+                    DiagnosticKind::Operands => wrong_operands(line),
+                    _ => e,
+                }
+            })
+        })
+        .collect()
+}
+
+/// The operands a pseudo-instruction expects, `None` when `mnemonic` isn't one.
+fn usage(mnemonic: &str) -> Option<&'static str> {
+    match mnemonic {
+        "nop" | "ret" => Some("no operands"),
+        "li" => Some("rd, imm"),
+        "la" => Some("rd, label"),
+        "call" | "j" => Some("label"),
+        "mv" | "not" | "neg" => Some("rd, rs"),
+        "beqz" | "bnez" => Some("rs, label"),
+        "bgt" | "ble" => Some("rs, rt, label"),
+        _ => None,
+    }
+}
+
+fn mnemonic(line: &Line) -> String {
+    line.mnemonic
+        .as_ref()
+        .map_or(String::new(), |(mnemonic, _)| mnemonic.to_lowercase())
+}
+
+fn wrong_operands(line: &Line) -> Diagnostic {
+    let (mnemonic, _) = line.mnemonic.as_ref().unwrap();
+    Diagnostic::new(
+        DiagnosticKind::Operands,
+        line.operands_span(),
+        format!(
+            "'{mnemonic}' expects '{}'",
+            usage(&mnemonic.to_lowercase()).unwrap_or_default()
+        ),
+    )
+}
+
+/// Splits `value` in the upper 20 bits for lui/auipc and the sign extended lower 12 bits to add to
+/// it.
+fn split_immediate(value: i32) -> (i32, i32) {
+    let lower = ((value & 0xFFF) ^ 0x800) - 0x800;
+    let upper = (value.wrapping_sub(lower) >> 12) & 0xFFFFF;
+    (upper, lower)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parser::parse_line;
+    use crate::assembly::Interpreter;
+
+    const EDGES: [i32; 10] = [
+        0,
+        2047,
+        2048,
+        -2048,
+        -2049,
+        0x7FF_FFFF,
+        0x1234_5800,
+        i32::MAX,
+        i32::MIN,
+        -1,
+    ];
+
+    #[test]
+    fn size_matches_expand() {
+        let labels = Labels::from([("start", 0), ("far", 3000)]);
+        let mut lines: Vec<String> = [
+            "nop",
+            "la t0, start",
+            "la t0, far",
+            "call far",
+            "mv t0, t1",
+            "not t0, t1",
+            "neg t0, t1",
+            "j start",
+            "ret",
+            "beqz t0, start",
+            "bnez t0, start",
+            "bgt t0, t1, start",
+            "ble t0, t1, start",
+            "addi t0, t0, 1",
+            "lui t0, 0xFFFFF",
+            "sw t0, 8(sp)",
+            "start: LI t0, 100000",
+        ]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+        lines.extend(EDGES.iter().map(|imm| format!("li t0, {imm}")));
+        for line in &lines {
+            let parsed = parse_line(line).unwrap();
+            let expanded = expand(&parsed, 1000, &labels, Backend::Software).unwrap();
+            assert_eq!(size(&parsed), expanded.len(), "{line}");
+        }
+    }
+
+    #[test]
+    fn split_immediate_adds_back_up() {
+        for value in EDGES {
+            let (upper, lower) = split_immediate(value);
+            assert!((0..=0xFFFFF).contains(&upper), "{value}");
+            assert!((-2048..=2047).contains(&lower), "{value}");
+            assert_eq!((upper << 12).wrapping_add(lower), value);
+        }
+    }
+
+    #[test]
+    fn li_loads_the_value() {
+        for value in EDGES {
+            let mut interpreter = Interpreter::new(vec![format!("li t0, {value}")]).unwrap();
+            while interpreter.step().unwrap().is_some() {}
+            assert_eq!(interpreter.registers[4], value);
+        }
+    }
+
+    #[test]
+    fn wrong_operands_point_at_the_source() {
+        for (line, columns, message) in [
+            ("li t0", 3..5, "'li' expects 'rd, imm'"),
+            ("MV t0, 5", 3..8, "'MV' expects 'rd, rs'"),
+            ("ret t0", 4..6, "'ret' expects 'no operands'"),
+            ("bgt t0, 1, start", 4..16, "'bgt' expects 'rs, rt, label'"),
+        ] {
+            let parsed = parse_line(line).unwrap();
+            let error = expand(&parsed, 0, &Labels::new(), Backend::Software).unwrap_err();
+            assert_eq!(error.kind, DiagnosticKind::Operands, "{line}");
+            assert_eq!(error.columns, columns, "{line}");
+            assert_eq!(error.message, message, "{line}");
+        }
+    }
+}
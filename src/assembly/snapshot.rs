@@ -0,0 +1,241 @@
+//! Saving a session to JSON and picking it up again later, after the board rebooted or on
+//! another student's board.
+//!
+//! ```json
+//! {"version":1,"source":["addi x5, x0, 3"],"instruction":1,"steps":1,"registers":[0,0,0,0,3,...],
+//!  "memory":"00000000...","breakpoints":[{"line":1,"condition":null}],"watchpoints":["x5"]}
+//! ```
+//!
+//! `memory` is hex, two digits per byte. The history [`Interpreter::step_back`] uses and a trace
+//! being recorded aren't saved.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::assembly::{Config, Interpreter, Register};
+
+/// Bumped when a snapshot changes in a way older builds can't read.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Everything needed to carry on with a program where it was.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub source: Vec<String>,
+    /// Index of the next instruction to run, counting real instructions from 0. Not a source
+    /// line, a pseudo-instruction can be more than one.
+    pub instruction: usize,
+    pub steps: u64,
+    /// x1 to x31.
+    pub registers: [i32; 31],
+    pub memory: String,
+    pub breakpoints: Vec<SnapshotBreakpoint>,
+    pub watchpoints: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotBreakpoint {
+    /// Source line, starting at 1.
+    pub line: usize,
+    pub condition: Option<String>,
+}
+
+impl Snapshot {
+    pub fn to_json(&self) -> String {
+        // Only strings and numbers, nothing that can fail to serialize
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        // The version first, another version can have other fields
+        let Versioned { version } =
+            serde_json::from_str(json).map_err(|e| format!("Not a snapshot: {e}"))?;
+        check_version(version)?;
+        serde_json::from_str(json).map_err(|e| format!("Not a snapshot: {e}"))
+    }
+}
+
+#[derive(Deserialize)]
+struct Versioned {
+    version: u32,
+}
+
+fn check_version(version: u32) -> Result<(), String> {
+    if version != SNAPSHOT_VERSION {
+        return Err(format!(
+            "Snapshot version {version} is not supported, only version {SNAPSHOT_VERSION} is"
+        ));
+    }
+    Ok(())
+}
+
+impl Interpreter {
+    pub fn snapshot(&self) -> Snapshot {
+        let mut memory = String::with_capacity(self.memory.len() * 2);
+        for byte in &self.memory {
+            let _ = write!(memory, "{byte:02x}");
+        }
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            source: self.source.clone(),
+            instruction: self.line,
+            steps: self.steps,
+            registers: self.registers,
+            memory,
+            breakpoints: self
+                .breakpoints
+                .iter()
+                .map(|(line, condition)| SnapshotBreakpoint {
+                    line: *line,
+                    condition: condition.map(|condition| condition.to_string()),
+                })
+                .collect(),
+            watchpoints: self
+                .watchpoints
+                .iter()
+                .map(|register| register.to_string())
+                .collect(),
+        }
+    }
+
+    /// Assembles the snapshot's program again and puts it back in the state it was saved in.
+    /// The memory is as big as it was when saved, whatever `config` says.
+    pub fn restore(snapshot: Snapshot, config: Config) -> Result<Self, String> {
+        check_version(snapshot.version)?;
+        let memory = parse_hex(&snapshot.memory)?;
+        let config = Config {
+            memory_size: memory.len(),
+            ..config
+        };
+        let mut interpreter = Interpreter::with_config(snapshot.source, config).map_err(|e| {
+            let problems: Vec<String> = e.iter().map(|diagnostic| diagnostic.to_string()).collect();
+            format!(
+                "The snapshot's program doesn't assemble: {}",
+                problems.join(", ")
+            )
+        })?;
+        if snapshot.instruction > interpreter.program.len() {
+            return Err(format!(
+                "The snapshot is at instruction {} but the program only has {}",
+                snapshot.instruction,
+                interpreter.program.len()
+            ));
+        }
+        interpreter.line = snapshot.instruction;
+        interpreter.steps = snapshot.steps;
+        interpreter.registers = snapshot.registers;
+        interpreter.memory = memory;
+        for breakpoint in snapshot.breakpoints {
+            interpreter.add_breakpoint(breakpoint.line, breakpoint.condition.as_deref())?;
+        }
+        for register in snapshot.watchpoints {
+            interpreter.watch(Register::from_str(&register)?);
+        }
+        Ok(interpreter)
+    }
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err("The memory should be two hex digits per byte".to_string());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("'{}' in the memory is not a hex byte", &hex[i..i + 2]))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interpreter() -> Interpreter {
+        let program = [
+            "li t0, 0x12345678",
+            "sw t0, -8(sp)",
+            "loop: addi t1, t1, 1",
+            "sb t1, -1(sp)",
+            "blt t1, t0, loop",
+        ];
+        let program = program.iter().map(|line| line.to_string()).collect();
+        Interpreter::with_config(program, Config::default()).unwrap()
+    }
+
+    #[test]
+    fn restores_what_it_saved() {
+        let mut interpreter = interpreter();
+        for _ in 0..6 {
+            interpreter.step().unwrap().unwrap();
+        }
+        interpreter.add_breakpoint(4, Some("x6 == 3")).unwrap();
+        interpreter.add_breakpoint(5, None).unwrap();
+        interpreter.watch(Register::X6);
+        let snapshot = interpreter.snapshot();
+        // Back at the loop, li is two instructions
+        assert_eq!(snapshot.instruction, 3);
+
+        let json = snapshot.to_json();
+        let mut restored =
+            Interpreter::restore(Snapshot::from_json(&json).unwrap(), Config::default()).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.registers, interpreter.registers);
+        assert_eq!(restored.memory(), interpreter.memory());
+        assert_eq!(restored.steps(), interpreter.steps());
+        assert_eq!(restored.source_line(), interpreter.source_line());
+        assert_eq!(restored.breakpoints(), interpreter.breakpoints());
+        assert_eq!(restored.watchpoints(), interpreter.watchpoints());
+        // And carries on the same
+        assert_eq!(restored.resume(), interpreter.resume());
+        assert_eq!(restored.snapshot(), interpreter.snapshot());
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION + 1,
+            ..interpreter().snapshot()
+        };
+        let error = Snapshot::from_json(&snapshot.to_json()).unwrap_err();
+        assert!(error.contains("version 2"), "{error}");
+        assert!(Interpreter::restore(snapshot, Config::default()).is_err());
+        // Whatever else a newer version has
+        assert!(Snapshot::from_json(r#"{"version":2,"something":"else"}"#)
+            .unwrap_err()
+            .contains("version 2"));
+    }
+
+    #[test]
+    fn broken_snapshots_are_refused() {
+        let snapshot = interpreter().snapshot();
+        for broken in [
+            Snapshot {
+                memory: "0".to_string(),
+                ..snapshot.clone()
+            },
+            Snapshot {
+                memory: "zz".to_string(),
+                ..snapshot.clone()
+            },
+            Snapshot {
+                instruction: 7,
+                ..snapshot.clone()
+            },
+            Snapshot {
+                source: vec!["nope".to_string()],
+                ..snapshot.clone()
+            },
+            Snapshot {
+                watchpoints: vec!["x32".to_string()],
+                ..snapshot.clone()
+            },
+        ] {
+            assert!(Interpreter::restore(broken, Config::default()).is_err());
+        }
+        assert!(Snapshot::from_json("{}").is_err());
+    }
+}
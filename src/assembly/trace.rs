@@ -0,0 +1,194 @@
+//! A log of every instruction a program ran, to diff a run against a reference simulator.
+//!
+//! It exports as JSON Lines, one object per step, or as a commit log in the format Spike prints
+//! with `--log-commits`:
+//!
+//! ```text
+//! core   0: 3 0x00000004 (0x00428293) x5  0x00000007
+//! core   0: 3 0x00000008 (0x00512023) mem 0x10000000 0x00000007
+//! ```
+//!
+//! Only the first `Config::trace_limit` steps are recorded. A trace that left steps out ends with
+//! `{"truncated":true}` as JSON Lines and `# truncated` in the commit log, so a diff shows where
+//! it stopped.
+
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::assembly::InstructionFormat::*;
+use crate::assembly::SupportedInstruction::*;
+use crate::assembly::{disassemble, Interpreter, Register, MEMORY_START};
+
+/// One instruction that ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    /// Counting from 1.
+    pub step: u64,
+    pub pc: u32,
+    /// Source line, starting at 1.
+    pub line: usize,
+    pub encoding: u32,
+    /// The register the instruction wrote and its new value, writes to x0 are left out.
+    pub register: Option<(Register, i32)>,
+    pub memory: Option<MemoryAccess>,
+}
+
+/// A load or store.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MemoryAccess {
+    pub address: u32,
+    /// In bytes.
+    pub width: u32,
+    /// What a store wrote, `None` for a load.
+    pub stored: Option<u32>,
+}
+
+/// Every step since [`Interpreter::start_trace`], up to the limit.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace {
+    pub entries: Vec<TraceEntry>,
+    /// Whether steps were left out because the trace was full.
+    pub truncated: bool,
+}
+
+impl Trace {
+    /// One JSON object per line, like
+    /// `{"step":1,"pc":0,"line":1,"instruction":"addi x5, x0, 3","encoding":"0x00300293","register":{"name":"x5","value":3},"memory":null}`.
+    pub fn to_json_lines(&self) -> String {
+        let mut json = String::new();
+        for entry in &self.entries {
+            let line = JsonEntry {
+                step: entry.step,
+                pc: entry.pc,
+                line: entry.line,
+                instruction: disassemble(entry.encoding),
+                encoding: format!("{:#010x}", entry.encoding),
+                register: entry.register.map(|(register, value)| JsonRegister {
+                    name: register.to_string(),
+                    value,
+                }),
+                memory: entry.memory,
+            };
+            // Numbers and strings, nothing that can fail
+            json.push_str(&serde_json::to_string(&line).unwrap());
+            json.push('\n');
+        }
+        if self.truncated {
+            json.push_str("{\"truncated\":true}\n");
+        }
+        json
+    }
+
+    /// Like Spike's `--log-commits`, always in machine mode on core 0.
+    pub fn to_commit_log(&self) -> String {
+        let mut log = String::new();
+        for entry in &self.entries {
+            let _ = write!(
+                log,
+                "core   0: 3 {:#010x} ({:#010x})",
+                entry.pc, entry.encoding
+            );
+            if let Some((register, value)) = entry.register {
+                let _ = write!(log, " {:<3} {value:#010x}", register.to_string());
+            }
+            if let Some(memory) = entry.memory {
+                let _ = write!(log, " mem {:#010x}", memory.address);
+                if let Some(stored) = memory.stored {
+                    let digits = memory.width as usize * 2;
+                    let _ = write!(log, " 0x{stored:0digits$x}");
+                }
+            }
+            log.push('\n');
+        }
+        if self.truncated {
+            log.push_str("# truncated\n");
+        }
+        log
+    }
+}
+
+#[derive(Serialize)]
+struct JsonEntry {
+    step: u64,
+    pc: u32,
+    line: usize,
+    instruction: String,
+    encoding: String,
+    register: Option<JsonRegister>,
+    memory: Option<MemoryAccess>,
+}
+
+#[derive(Serialize)]
+struct JsonRegister {
+    name: String,
+    value: i32,
+}
+
+impl Interpreter {
+    /// Records every step from now on, dropping what was recorded before.
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Trace::default());
+    }
+
+    /// Stops recording and hands over what was recorded.
+    pub fn stop_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    /// What's known about the next instruction before it runs, `None` when not recording.
+    pub(crate) fn trace_entry(&self) -> Option<TraceEntry> {
+        self.trace.as_ref()?;
+        let instruction = self.program[self.line];
+        let memory = match instruction.instruction {
+            Lb | Lh | Lw | Lbu | Lhu | Sb | Sh | Sw => {
+                self.access_range(&instruction)
+                    .ok()
+                    .map(|range| MemoryAccess {
+                        address: MEMORY_START + range.start as u32,
+                        width: range.len() as u32,
+                        stored: None,
+                    })
+            }
+            _ => None,
+        };
+        Some(TraceEntry {
+            step: self.steps + 1,
+            pc: (self.line * 4) as u32,
+            line: self.source_lines[self.line],
+            encoding: instruction.encode().unwrap_or(0),
+            register: None,
+            memory,
+        })
+    }
+
+    /// Fills in what the instruction wrote and adds it to the trace, when there's room.
+    pub(crate) fn record(&mut self, mut entry: TraceEntry) {
+        let instruction = self.program[entry.pc as usize / 4];
+        match instruction.instruction.info().format {
+            S | SB => {}
+            _ if instruction.rd == Register::X0 => {}
+            _ => entry.register = Some((instruction.rd, self.registers(&instruction.rd))),
+        }
+        if let Some(memory) = &mut entry.memory
+            && matches!(instruction.instruction, Sb | Sh | Sw)
+        {
+            let start = (memory.address - MEMORY_START) as usize;
+            let width = memory.width as usize;
+            let mut bytes = [0; 4];
+            bytes[..width].copy_from_slice(&self.memory[start..start + width]);
+            memory.stored = Some(u32::from_le_bytes(bytes));
+        }
+        if let Some(trace) = &mut self.trace {
+            if trace.entries.len() < self.trace_limit {
+                trace.entries.push(entry);
+            } else {
+                trace.truncated = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Assembles a file and runs it, to script exercises and check submissions without a browser.
+//!
+//! ```text
+//! cli <program.s> [run|step|registers|trace|trace-json|replay <recording>]
+//! ```
+//!
+//! `run`, the default, runs the program to the end. `registers` does the same and prints the
+//! registers afterwards, `step` stops before every instruction and waits for a command. `trace`
+//! runs it and prints a Spike style commit log, `trace-json` the same trace as JSON Lines.
+//! `replay` runs it against what the checked backend recorded on the board, like its serial
+//! log, and stops where the hardware and software disagree. Exits with 1 when the program
+//! doesn't assemble or stops with an error.
+
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+use std::{env, fs};
+
+use risc_esp_asm::assembly::{
+    Config, Diagnostic, Differential, Executor, Interpreter, Register, Replay,
+};
+
+enum Mode {
+    Run,
+    Step,
+    Registers,
+    Trace,
+    TraceJson,
+    /// With the path of the recording.
+    Replay(String),
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (path, mode) = match args.as_slice() {
+        [path] => (path, Mode::Run),
+        [path, mode] => match mode.as_str() {
+            "run" => (path, Mode::Run),
+            "step" => (path, Mode::Step),
+            "registers" => (path, Mode::Registers),
+            "trace" => (path, Mode::Trace),
+            "trace-json" => (path, Mode::TraceJson),
+            _ => return usage(),
+        },
+        [path, mode, recording] if mode == "replay" => (path, Mode::Replay(recording.clone())),
+        _ => return usage(),
+    };
+
+    let source = match read(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    let lines: Vec<String> = source.lines().map(String::from).collect();
+    let assembled = match &mode {
+        Mode::Replay(recording_path) => {
+            let recording = match read(recording_path) {
+                Ok(recording) => recording,
+                Err(code) => return code,
+            };
+            let replay = match Replay::parse(&recording) {
+                Ok(replay) => replay,
+                Err(e) => {
+                    eprintln!("{recording_path}: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let executor: Box<dyn Executor> = Box::new(Differential::new(Box::new(replay)));
+            Interpreter::with_executor(lines.clone(), Config::default(), executor)
+        }
+        _ => Interpreter::new(lines.clone()),
+    };
+    let mut interpreter = match assembled {
+        Ok(interpreter) => interpreter,
+        Err(diagnostics) => {
+            for diagnostic in &diagnostics {
+                print_diagnostic(path, &lines, diagnostic);
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Mode::Trace | Mode::TraceJson = mode {
+        interpreter.start_trace();
+    }
+    let result = match mode {
+        Mode::Step => step_interactively(&mut interpreter, &lines),
+        _ => run(&mut interpreter),
+    };
+    // What ran up to an error is the interesting part of a trace
+    if let Some(trace) = interpreter.trace() {
+        match mode {
+            Mode::TraceJson => print!("{}", trace.to_json_lines()),
+            _ => print!("{}", trace.to_commit_log()),
+        }
+        if trace.truncated {
+            eprintln!(
+                "{path}: Only the first {} steps are in the trace",
+                trace.entries.len()
+            );
+        }
+    }
+    if let Err(e) = result {
+        eprintln!("{path}: {e}");
+        return ExitCode::FAILURE;
+    }
+    if let Mode::Registers = mode {
+        print_registers(&interpreter.registers);
+    }
+    ExitCode::SUCCESS
+}
+
+fn usage() -> ExitCode {
+    eprintln!("Usage: cli <program.s> [run|step|registers|trace|trace-json|replay <recording>]");
+    ExitCode::FAILURE
+}
+
+fn read(path: &str) -> Result<String, ExitCode> {
+    fs::read_to_string(path).map_err(|e| {
+        eprintln!("Can't read {path}: {e}");
+        ExitCode::FAILURE
+    })
+}
+
+/// Steps once, with the line of the instruction in the error.
+fn step(interpreter: &mut Interpreter) -> Result<Option<()>, String> {
+    let line = interpreter.source_line().unwrap_or(0);
+    interpreter
+        .step()
+        .map_err(|e| format!("Error on line {line}: {e}"))
+}
+
+fn run(interpreter: &mut Interpreter) -> Result<(), String> {
+    while step(interpreter)?.is_some() {}
+    finished(interpreter)
+}
+
+/// Running out of budget before the end is an error, the program probably loops forever.
+fn finished(interpreter: &Interpreter) -> Result<(), String> {
+    if interpreter.source_line().is_some() && interpreter.budget_exhausted() {
+        return Err(format!(
+            "Stopped on line {} after {} steps, does the program loop forever?",
+            interpreter.source_line().unwrap_or(0),
+            interpreter.steps()
+        ));
+    }
+    Ok(())
+}
+
+fn step_interactively(interpreter: &mut Interpreter, lines: &[String]) -> Result<(), String> {
+    let mut input = io::stdin().lock().lines();
+    loop {
+        finished(interpreter)?;
+        let Some(line) = interpreter.source_line() else {
+            println!("Program finished");
+            return Ok(());
+        };
+        print!(
+            "{line:>4} | {}\n[enter] step, [r]egisters, [c]ontinue, [q]uit > ",
+            lines[line - 1].trim()
+        );
+        io::stdout().flush().map_err(|e| e.to_string())?;
+
+        let Some(Ok(command)) = input.next() else {
+            return Ok(());
+        };
+        match command.trim() {
+            "" => {
+                let before = interpreter.registers;
+                step(interpreter)?;
+                print_changes(&before, &interpreter.registers);
+            }
+            "r" => print_registers(&interpreter.registers),
+            "c" => return run(interpreter),
+            "q" => return Ok(()),
+            other => println!("Unknown command '{other}'"),
+        }
+    }
+}
+
+/// Like a compiler would: where, what, the line with the problem underlined and a hint.
+fn print_diagnostic(path: &str, lines: &[String], diagnostic: &Diagnostic) {
+    let Diagnostic {
+        kind,
+        line,
+        columns,
+        message,
+        hint,
+    } = diagnostic;
+    eprintln!(
+        "{path}:{line}:{}: {message} [{}]",
+        columns.start + 1,
+        kind.as_str()
+    );
+    if let Some(source) = lines.get(line - 1) {
+        eprintln!("{line:>4} | {source}");
+        eprintln!(
+            "     | {}{}",
+            " ".repeat(columns.start),
+            "^".repeat(columns.len().max(1))
+        );
+    }
+    if let Some(hint) = hint {
+        eprintln!("     = hint: {hint}");
+    }
+}
+
+fn register_name(register: Register) -> String {
+    format!("x{} ({})", register.to_code(), register.abi_name())
+}
+
+/// x1 to x31, x0 is always 0.
+fn print_registers(registers: &[i32; 31]) {
+    for (register, value) in Register::ALL[1..].iter().zip(registers) {
+        println!("{:<12} {value:>11} {value:#010x}", register_name(*register));
+    }
+}
+
+fn print_changes(before: &[i32; 31], after: &[i32; 31]) {
+    for ((register, old), new) in Register::ALL[1..].iter().zip(before).zip(after) {
+        if old != new {
+            println!("       {}: {old} -> {new}", register_name(*register));
+        }
+    }
+}
@@ -0,0 +1,16 @@
+//! Serves the web page on a normal computer, running programs with the software backend.
+//! The address to listen on can be given as the first argument.
+
+use std::env;
+use std::net::TcpListener;
+
+use risc_esp_asm::server;
+
+fn main() {
+    let address = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    let listener = TcpListener::bind(&address).unwrap();
+    println!("Serving on http://{address}");
+    server::serve(listener);
+}
@@ -0,0 +1,266 @@
+//! Debugger in the terminal, the web page for SSH or a serial console: the source with the
+//! current line highlighted next to the registers, with the ones the last step changed marked.
+//!
+//! ```text
+//! tui <program.s>
+//! ```
+//!
+//! `s` or enter steps, `c` continues to the next breakpoint or the end, the arrow keys pick a line
+//! and `b` toggles a breakpoint on it, `r` resets the program and `q` quits.
+
+use std::io::{self, Write};
+use std::process::ExitCode;
+use std::{env, fs};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use risc_esp_asm::assembly::{disassemble, Interpreter, Register, StopReason};
+
+fn main() -> ExitCode {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("Usage: tui <program.s>");
+        return ExitCode::FAILURE;
+    };
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Can't read {path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let lines: Vec<String> = source.lines().map(String::from).collect();
+    let interpreter = match Interpreter::new(lines.clone()) {
+        Ok(interpreter) => interpreter,
+        Err(diagnostics) => {
+            for diagnostic in diagnostics {
+                eprintln!("{path}: {diagnostic}");
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut debugger = Debugger {
+        path,
+        lines,
+        interpreter,
+        cursor: 1,
+        top: 1,
+        changed: [false; 31],
+        status: "Ready".to_string(),
+    };
+    debugger.cursor = debugger.interpreter.source_line().unwrap_or(1);
+
+    let mut out = io::stdout();
+    let result = terminal::enable_raw_mode()
+        .and_then(|_| execute!(out, EnterAlternateScreen, Hide))
+        .and_then(|_| debugger.run(&mut out));
+    // Give the terminal back whatever happened
+    let _ = execute!(out, Show, LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+struct Debugger {
+    path: String,
+    lines: Vec<String>,
+    interpreter: Interpreter,
+    /// The line breakpoints are toggled on, starting at 1.
+    cursor: usize,
+    /// First line on screen, starting at 1.
+    top: usize,
+    /// Registers, x1 to x31, the last step or continue changed.
+    changed: [bool; 31],
+    status: String,
+}
+
+impl Debugger {
+    fn run(&mut self, out: &mut impl Write) -> io::Result<()> {
+        loop {
+            self.draw(out)?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('s') | KeyCode::Enter => self.step(),
+                KeyCode::Char('c') => self.continue_to_breakpoint(),
+                KeyCode::Char('b') => self.toggle_breakpoint(),
+                KeyCode::Char('r') => self.reset(),
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Up => self.cursor = self.cursor.saturating_sub(1).max(1),
+                KeyCode::Down => self.cursor = (self.cursor + 1).min(self.lines.len().max(1)),
+                _ => {}
+            }
+        }
+    }
+
+    fn step(&mut self) {
+        let before = self.interpreter.registers;
+        let line = self.interpreter.source_line().unwrap_or(0);
+        let encoding = self.interpreter.current_encoding().unwrap_or(0);
+        self.status = match self.interpreter.step() {
+            Ok(Some(())) => format!("Ran {} on line {line}", disassemble(encoding)),
+            // Instructions left, so the budget ran out
+            Ok(None) if self.interpreter.source_line().is_some() => self.budget_exhausted(),
+            Ok(None) => "Program finished, r to run it again".to_string(),
+            Err(e) => format!("Error on line {line}: {e}"),
+        };
+        self.after_running(&before);
+    }
+
+    fn continue_to_breakpoint(&mut self) {
+        let before = self.interpreter.registers;
+        self.status = match self.interpreter.resume() {
+            Ok(StopReason::Finished) => "Program finished, r to run it again".to_string(),
+            Ok(StopReason::BudgetExhausted) => self.budget_exhausted(),
+            Ok(StopReason::Breakpoint { line }) => {
+                format!("Stopped at the breakpoint on line {line}")
+            }
+            Ok(StopReason::Watchpoint { register, old, new }) => {
+                format!("Stopped because {register} changed from {old} to {new}")
+            }
+            Err(e) => format!(
+                "Error on line {}: {e}",
+                self.interpreter.source_line().unwrap_or(0)
+            ),
+        };
+        self.after_running(&before);
+    }
+
+    fn budget_exhausted(&self) -> String {
+        format!(
+            "Stopped after {} steps, does the program loop forever? r to start over",
+            self.interpreter.steps()
+        )
+    }
+
+    fn toggle_breakpoint(&mut self) {
+        if self.interpreter.remove_breakpoint(self.cursor) {
+            return;
+        }
+        if let Err(e) = self.interpreter.add_breakpoint(self.cursor, None) {
+            self.status = e;
+        }
+    }
+
+    /// Marks what changed and moves the cursor along with the program.
+    fn after_running(&mut self, before: &[i32; 31]) {
+        for (changed, (old, new)) in self
+            .changed
+            .iter_mut()
+            .zip(before.iter().zip(&self.interpreter.registers))
+        {
+            *changed = old != new;
+        }
+        if let Some(line) = self.interpreter.source_line() {
+            self.cursor = line;
+        }
+    }
+
+    fn reset(&mut self) {
+        // It assembled the first time, it will again
+        let mut interpreter = Interpreter::new(self.lines.clone()).unwrap();
+        for (line, condition) in self.interpreter.breakpoints() {
+            let condition = condition.map(|condition| condition.to_string());
+            interpreter
+                .add_breakpoint(*line, condition.as_deref())
+                .unwrap();
+        }
+        self.interpreter = interpreter;
+        self.changed = [false; 31];
+        self.cursor = self.interpreter.source_line().unwrap_or(1);
+        self.status = "Reset".to_string();
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        // Title, a blank line, the source and the status at the bottom
+        let rows = height.saturating_sub(3).max(1);
+        let registers_width = 2 * 26;
+        let source_width = width.saturating_sub(registers_width + 2).max(20);
+
+        if self.cursor < self.top {
+            self.top = self.cursor;
+        } else if self.cursor >= self.top + rows {
+            self.top = self.cursor + 1 - rows;
+        }
+
+        queue!(
+            out,
+            Clear(ClearType::All),
+            MoveTo(0, 0),
+            SetAttribute(Attribute::Bold),
+            Print(format!(
+                "{} - [s]tep [c]ontinue [b]reakpoint [r]eset [q]uit",
+                self.path
+            )),
+            SetAttribute(Attribute::Reset),
+        )?;
+
+        let current = self.interpreter.source_line();
+        for (row, number) in (self.top..=self.lines.len()).take(rows).enumerate() {
+            let breakpoint = if self.interpreter.breakpoints().contains_key(&number) {
+                '*'
+            } else {
+                ' '
+            };
+            let arrow = if current == Some(number) { '>' } else { ' ' };
+            let text: String = format!(
+                "{breakpoint}{arrow}{number:>4} | {}",
+                self.lines[number - 1]
+            )
+            .chars()
+            .take(source_width)
+            .collect();
+            queue!(out, MoveTo(0, (row + 2) as u16))?;
+            if current == Some(number) {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            if self.cursor == number {
+                queue!(out, SetAttribute(Attribute::Underlined))?;
+            }
+            queue!(out, Print(text), SetAttribute(Attribute::Reset))?;
+        }
+
+        let column = source_width + 2;
+        for (i, register) in Register::ALL[1..].iter().enumerate() {
+            let value = self.interpreter.registers[i];
+            let name = format!("x{} ({})", register.to_code(), register.abi_name());
+            let x = column + (i / 16) * 26;
+            queue!(out, MoveTo(x as u16, (i % 16 + 2) as u16))?;
+            if self.changed[i] {
+                queue!(
+                    out,
+                    SetForegroundColor(Color::Yellow),
+                    SetAttribute(Attribute::Bold),
+                    Print(format!("{name:<12}{value:>11} *")),
+                    SetAttribute(Attribute::Reset),
+                    ResetColor,
+                )?;
+            } else {
+                queue!(out, Print(format!("{name:<12}{value:>11}")))?;
+            }
+        }
+
+        let status: String = self.status.chars().take(width).collect();
+        queue!(
+            out,
+            MoveTo(0, height.saturating_sub(1) as u16),
+            Print(status)
+        )?;
+        out.flush()
+    }
+}
@@ -0,0 +1,4 @@
+#![feature(let_chains)]
+
+pub mod assembly;
+pub mod server;
@@ -0,0 +1,1067 @@
+//! The web front-end: the page and the JSON API it uses to assemble and step through programs.
+
+use std::io::{self, BufReader, Read};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+use crate::assembly::{disassemble, Backend, Config, Interpreter, Register, Snapshot};
+
+use api::{
+    BudgetExhausted, Done, Ended, Errors, NewProgram, Position, Stepped, Stop, Stopped, Stops,
+    Tracing,
+};
+use http::{read_request, Request, Response};
+use sessions::{Missing, Sessions};
+
+pub use sessions::SessionConfig;
+
+mod api;
+mod http;
+mod sessions;
+
+type SharedSessions = Mutex<Sessions>;
+
+/// How long a request may take to arrive and its answer to be taken, over slow WiFi too. A
+/// worker waits on nothing else, so a client that stalls only holds one up for this long.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+/// The main task gets by with 8000 bytes (see `sdkconfig.defaults`), with some to spare.
+#[cfg(target_arch = "riscv32")]
+const WORKER_STACK_SIZE: usize = 16 * 1024;
+/// What Rust gives threads on a computer, printing a panic's backtrace needs more than 16 KiB.
+#[cfg(not(target_arch = "riscv32"))]
+const WORKER_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Connections answered at the same time, each on its own thread.
+    pub workers: usize,
+    pub sessions: SessionConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            sessions: SessionConfig::default(),
+        }
+    }
+}
+
+/// Answers requests on `listener` forever, a few connections at a time.
+pub fn serve(listener: TcpListener) {
+    serve_with_config(listener, ServerConfig::default())
+}
+
+/// The calling thread is one of the workers, so there's always at least one even when no
+/// threads can be started.
+pub fn serve_with_config(listener: TcpListener, config: ServerConfig) {
+    let sessions: SharedSessions = Mutex::new(Sessions::new(config.sessions));
+    thread::scope(|scope| {
+        for worker in 1..config.workers {
+            let started = thread::Builder::new()
+                .name(format!("http-{worker}"))
+                .stack_size(WORKER_STACK_SIZE)
+                .spawn_scoped(scope, || work(&listener, &sessions));
+            if let Err(e) = started {
+                log::warn!("Can't start worker {worker}, serving with fewer: {e}");
+                break;
+            }
+        }
+        work(&listener, &sessions);
+    });
+}
+
+/// Takes the next connection that comes in, whichever worker is free gets it. A request that
+/// panics gets a 500 and the worker carries on with the next one.
+fn work(listener: &TcpListener, sessions: &SharedSessions) {
+    for stream in listener.incoming() {
+        // A connection that failed before it was accepted is the client's problem
+        let Ok(stream) = stream else { continue };
+        let handled =
+            panic::catch_unwind(AssertUnwindSafe(|| handle_connection(&stream, sessions)));
+        if handled.is_err() {
+            let _ = Response::error(500, "The request crashed the server").write_to(&mut &stream);
+        }
+    }
+}
+
+/// Locks the sessions. A worker that panicked while holding them didn't leave them half
+/// changed, every change is a single map operation.
+fn lock(sessions: &SharedSessions) -> MutexGuard<'_, Sessions> {
+    sessions.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Answers a single request. The page sends one per click, so a connection isn't kept alive to
+/// sit idle with a worker to itself.
+fn handle_connection(stream: &TcpStream, sessions: &SharedSessions) {
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    if stream.set_write_timeout(Some(REQUEST_TIMEOUT)).is_err() {
+        return;
+    }
+    let mut reader = BufReader::new(Deadline { stream, deadline });
+    let response = match read_request(&mut reader) {
+        Ok(Some(request)) => route(&request, sessions),
+        Ok(None) => return,
+        Err(response) => response,
+    };
+    let _ = response.write_to(&mut &*stream);
+}
+
+/// Reads from a stream until `deadline`, a client trickling in a byte at a time runs out of time
+/// all the same.
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+fn route(request: &Request, sessions: &SharedSessions) -> Response {
+    let method = request.method.as_str();
+    let segments = request.segments();
+    let allowed: &[&'static str] = match segments.as_slice() {
+        [] => &["GET"],
+        ["new"] | ["restore"] => &["POST"],
+        [_] => &["POST", "DELETE"],
+        [_, "continue"] | [_, "back"] => &["POST"],
+        [_, "breakpoints", _] | [_, "watchpoints", _] => &["POST", "DELETE"],
+        [_, "trace"] => &["GET", "POST", "DELETE"],
+        [_, "snapshot"] => &["GET"],
+        _ => return Response::not_found(),
+    };
+    if !allowed.contains(&method) {
+        return Response::method_not_allowed(allowed);
+    }
+
+    let id = match segments.as_slice() {
+        [] => return get_resp(),
+        ["new"] => return new_program(request, sessions),
+        ["restore"] => return restore(sessions, request),
+        [id, ..] => *id,
+    };
+    if let ("DELETE", [_]) = (method, segments.as_slice()) {
+        return match lock(sessions).remove(id) {
+            Ok(()) => Response::json(&Ended { ended: true }),
+            Err(missing) => missing_session(missing),
+        };
+    }
+    let session = match lock(sessions).get(id) {
+        Ok(session) => session,
+        Err(missing) => return missing_session(missing),
+    };
+    let Ok(mut interpreter) = session.lock() else {
+        return crashed(sessions, id);
+    };
+    let interpreter = &mut *interpreter;
+    match (method, &segments[1..]) {
+        (_, []) => step(interpreter, id),
+        (_, ["continue"]) => resume(interpreter, id),
+        (_, ["back"]) => back(interpreter, id, request.query("to")),
+        (method, ["breakpoints", line]) => {
+            breakpoint(interpreter, method, line, request.query("if"))
+        }
+        (method, ["watchpoints", register]) => watchpoint(interpreter, method, register),
+        (method, ["trace"]) => trace(interpreter, method, request.query("format")),
+        _ => Response::json(&interpreter.snapshot()),
+    }
+}
+
+fn missing_session(missing: Missing) -> Response {
+    match missing {
+        Missing::Expired => Response::error(
+            410,
+            "Session expired, the program was left alone for too long. Run it again",
+        ),
+        Missing::Unknown => {
+            Response::error(404, "There is no such session, it ended or never started")
+        }
+    }
+}
+
+/// A request for the session panicked halfway, what state it left the program in is unknown.
+/// The session is ended so it doesn't keep its place.
+fn crashed(sessions: &SharedSessions, id: &str) -> Response {
+    let _ = lock(sessions).remove(id);
+    Response::error(
+        500,
+        "The program crashed the server and was ended, run it again",
+    )
+}
+
+/// Adds a session for `interpreter` and answers with what `first` does with it. When `first`
+/// answers with an `Err` there's nothing left to do with the program and the session is ended.
+fn start_session(
+    sessions: &SharedSessions,
+    interpreter: Interpreter,
+    first: impl FnOnce(&mut Interpreter, &str) -> Result<Response, Response>,
+) -> Response {
+    let session = {
+        let mut sessions = lock(sessions);
+        let Some(id) = sessions.insert(interpreter) else {
+            return Response::error(
+                503,
+                &format!(
+                    "Already running {} programs, try again when one is done",
+                    sessions.max_sessions()
+                ),
+            );
+        };
+        sessions.get(&id).map(|session| (id, session))
+    };
+    let (id, session) = match session {
+        Ok(session) => session,
+        Err(missing) => return missing_session(missing),
+    };
+    let Ok(mut interpreter) = session.lock() else {
+        return crashed(sessions, &id);
+    };
+    match first(&mut interpreter, &id) {
+        Ok(response) => response,
+        Err(response) => {
+            let _ = lock(sessions).remove(&id);
+            response
+        }
+    }
+}
+
+/// Assembles a [`NewProgram`] and takes its first step.
+fn new_program(request: &Request, sessions: &SharedSessions) -> Response {
+    let program: NewProgram = match serde_json::from_slice(&request.body) {
+        Ok(program) => program,
+        Err(e) => return Response::error(400, &format!("Not a program: {e}")),
+    };
+    let backend = match backend(program.backend.as_deref()) {
+        Ok(backend) => backend,
+        Err(e) => return Response::error(400, &e),
+    };
+    let mut registers = Vec::new();
+    for (name, value) in &program.registers {
+        match Register::from_str(name) {
+            // t0 to t2 and t3 to t6, the firmware and the trampoline need the others
+            Ok(register) if matches!(register.to_code(), 5..=7 | 28..=31) => {
+                registers.push((register, *value))
+            }
+            Ok(register) => {
+                return Response::error(
+                    400,
+                    &format!("Only t0 to t6 can start with a value, not {register}"),
+                )
+            }
+            Err(e) => return Response::error(400, &e),
+        }
+    }
+    // Keep blank lines so line numbers match the editor and leading whitespace so columns do
+    let source: Vec<String> = program.source.lines().map(String::from).collect();
+
+    let config = Config {
+        backend,
+        ..Config::default()
+    };
+    match Interpreter::with_config(source, config) {
+        Ok(mut interpreter) => {
+            for (register, value) in registers {
+                interpreter.registers[register.to_code() as usize - 1] = value;
+            }
+            start_session(sessions, interpreter, first_step)
+        }
+        Err(diagnostics) => Response::json(&Errors::new(&diagnostics)),
+    }
+}
+
+/// Carries on with a program from a snapshot as a new program, answering like a step back.
+fn restore(sessions: &SharedSessions, request: &Request) -> Response {
+    let backend = match backend(request.query("backend").as_deref()) {
+        Ok(backend) => backend,
+        Err(e) => return Response::error(400, &e),
+    };
+    let Ok(snapshot) = std::str::from_utf8(&request.body) else {
+        return Response::error(400, "The snapshot isn't UTF-8");
+    };
+    let config = Config {
+        backend,
+        ..Config::default()
+    };
+    let interpreter =
+        Snapshot::from_json(snapshot).and_then(|snapshot| Interpreter::restore(snapshot, config));
+    match interpreter {
+        Ok(interpreter) => start_session(sessions, interpreter, |interpreter, id| {
+            Ok(Response::json(&Position::new(interpreter, id)))
+        }),
+        Err(e) => Response::failed(e),
+    }
+}
+
+/// `hardware`, `software` or `checked`, the CPU when there is one and nothing was asked for.
+/// Without a CPU everything runs in software.
+fn backend(name: Option<&str>) -> Result<Backend, String> {
+    match name {
+        #[cfg(target_arch = "riscv32")]
+        Some("checked") => Ok(Backend::Checked),
+        #[cfg(not(target_arch = "riscv32"))]
+        Some("checked") => Ok(Backend::default()),
+        Some("software") => Ok(Backend::Software),
+        Some("hardware") | None => Ok(Backend::default()),
+        Some(name) => Err(format!(
+            "'{name}' is not a backend, use hardware, software or checked"
+        )),
+    }
+}
+
+fn step(interpreter: &mut Interpreter, session: &str) -> Response {
+    let line = interpreter.source_line().unwrap_or(0); //Send the line we are going to execute
+    let encoding = interpreter.current_encoding().unwrap_or(0);
+    match interpreter.step() {
+        Ok(Some(_)) => Response::json(&Stepped {
+            line,
+            registers: interpreter.registers,
+            session: session.to_string(),
+            instruction: disassemble(encoding),
+            encoding: format!("{encoding:#010x}"),
+        }),
+        // Not stepping with instructions left means the budget ran out, one that ran out on
+        // the last instruction still finished
+        Ok(None) if interpreter.source_line().is_some() => Response::json(&BudgetExhausted {
+            budget_exhausted: true,
+            line,
+            steps: interpreter.steps(),
+        }),
+        Ok(None) => Response::json(&Done { done: true }),
+        Err(e) => Response::failed(format!("Error on line {line}: {e}")),
+    }
+}
+
+/// The first step of a new program. Only a step that ran answers with the session, a program
+/// that failed or was done straight away doesn't keep one.
+fn first_step(interpreter: &mut Interpreter, session: &str) -> Result<Response, Response> {
+    let steps = interpreter.steps();
+    let response = step(interpreter, session);
+    if interpreter.steps() > steps {
+        Ok(response)
+    } else {
+        Err(response)
+    }
+}
+
+/// Runs until a breakpoint, watchpoint or the end, answering like a step with why it stopped.
+fn resume(interpreter: &mut Interpreter, session: &str) -> Response {
+    match interpreter.resume() {
+        Ok(stop) => Response::json(&Stopped {
+            line: interpreter.source_line().unwrap_or(0),
+            registers: interpreter.registers,
+            session: session.to_string(),
+            stop: Stop::new(stop, interpreter),
+        }),
+        Err(e) => Response::failed(format!(
+            "Error on line {}: {e}",
+            interpreter.source_line().unwrap_or(0)
+        )),
+    }
+}
+
+/// Undoes the last step, or with `?to=` goes back to after that many steps. Answers with the
+/// line that runs next.
+fn back(interpreter: &mut Interpreter, session: &str, to: Option<String>) -> Response {
+    let result = match to {
+        Some(to) => match to.parse::<u64>() {
+            Ok(to) => interpreter.rewind_to(to),
+            Err(_) => Err(format!("'{to}' is not a step")),
+        },
+        None if interpreter.step_back() => Ok(()),
+        None => Err("Already at the start, or further back than is remembered".to_string()),
+    };
+    match result {
+        Ok(()) => Response::json(&Position::new(interpreter, session)),
+        Err(e) => Response::failed(e),
+    }
+}
+
+/// `POST` starts recording a trace, `DELETE` stops and `GET` exports it as JSON Lines or with
+/// `?format=commit` as a Spike style commit log.
+fn trace(interpreter: &mut Interpreter, method: &str, format: Option<String>) -> Response {
+    match method {
+        "POST" => {
+            interpreter.start_trace();
+            Response::json(&Tracing {
+                tracing: true,
+                truncated: false,
+            })
+        }
+        "DELETE" => {
+            let truncated = interpreter
+                .stop_trace()
+                .is_some_and(|trace| trace.truncated);
+            Response::json(&Tracing {
+                tracing: false,
+                truncated,
+            })
+        }
+        _ => match interpreter.trace() {
+            Some(trace) if format.as_deref() == Some("commit") => {
+                Response::new("text/plain", trace.to_commit_log())
+            }
+            Some(trace) => Response::new("application/jsonl", trace.to_json_lines()),
+            None => {
+                Response::failed("Not recording a trace, start one with POST first".to_string())
+            }
+        },
+    }
+}
+
+/// `POST` adds a breakpoint on `line`, with the condition from `?if=`, `DELETE` removes it.
+/// Answers with all breakpoints and watchpoints.
+fn breakpoint(
+    interpreter: &mut Interpreter,
+    method: &str,
+    line: &str,
+    condition: Option<String>,
+) -> Response {
+    let Ok(line) = line.parse::<usize>() else {
+        return Response::failed(format!("'{line}' is not a line"));
+    };
+    let result = match method {
+        "POST" => interpreter.add_breakpoint(line, condition.as_deref()),
+        _ if interpreter.remove_breakpoint(line) => Ok(()),
+        _ => Err(format!("There is no breakpoint on line {line}")),
+    };
+    match result {
+        Ok(()) => Response::json(&Stops::new(interpreter)),
+        Err(e) => Response::failed(e),
+    }
+}
+
+/// `POST` watches `register`, `DELETE` stops watching it. Answers with all breakpoints and
+/// watchpoints.
+fn watchpoint(interpreter: &mut Interpreter, method: &str, register: &str) -> Response {
+    let register = match Register::from_str(register) {
+        Ok(register) => register,
+        Err(e) => return Response::failed(e),
+    };
+    if method == "DELETE" && !interpreter.unwatch(register) {
+        return Response::failed(format!("{register} isn't watched"));
+    }
+    if method == "POST" {
+        interpreter.watch(register);
+    }
+    Response::json(&Stops::new(interpreter))
+}
+
+fn get_resp() -> Response {
+    let contents = r#"<!doctype html>
+    <html lang="en-US">
+    
+    <head>
+        <meta charset="utf-8" />
+        <meta name="viewport" content="width=device-width" />
+        <title>RISC-V executor</title>
+        <style>
+            .error {
+                background-color: red;
+                visibility: hidden;
+            }
+    
+            .container {
+                display: flex;
+            }
+    
+            /* Thanks https://webtips.dev/add-line-numbers-to-html-textarea ! */
+            .editor {
+                display: inline-flex;
+                gap: 10px;
+                font-family: monospace;
+                line-height: 21px;
+                border-radius: 2px;
+                padding: 20px 10px;
+                overflow: auto;
+            }
+    
+            textarea {
+                line-height: 21px;
+                overflow-y: hidden;
+                padding: 0;
+                border: 0;
+                min-width: 500px;
+                outline: none;
+                resize: none;
+            }
+    
+            .line-numbers {
+                width: 20px;
+                text-align: right;
+            }
+    
+            .line-numbers span {
+                counter-increment: linenumber;
+            }
+    
+            .line-numbers span::before {
+                content: counter(linenumber);
+                display: block;
+            }
+    
+            .line-numbers .error-line::before {
+                text-decoration: underline wavy red;
+            }
+    
+            .line-numbers .executed::before {
+                background-color: red;
+            }
+
+            .line-numbers span {
+                cursor: pointer;
+            }
+
+            .line-numbers .breakpoint::before {
+                color: white;
+                background-color: blue;
+            }
+        </style>
+    </head>
+    
+    <body>
+        <div id="error" class="error">
+            <p>Errors here</p>
+            <div id="diagnostics"></div>
+        </div>
+    
+        <div class="container">
+            <label for="program">Enter your assembly here:</label>
+    
+            <div class="editor">
+                <div class="line-numbers">
+                    <span class="line-number"></span>
+                </div>
+                <textarea id="program" name="program" rows="20" cols="33">
+    addi x5, x5, 10
+            </textarea>
+            </div>
+    
+            <button onclick="step_back()">Back</button>
+            <button onclick="step()">Step</button>
+            <button onclick="continue_program()">Continue</button>
+            <label for="backend">Run on:</label>
+            <select id="backend">
+                <option value="hardware">the CPU</option>
+                <option value="software">software emulation</option>
+                <option value="checked">the CPU, checked against software</option>
+            </select>
+
+            <p>Sent to the CPU: <code id="executed"></code></p>
+            <p>Click a line number for a breakpoint, shift-click for one with a condition like <code>x5 == 10</code>. <span id="stopped"></span></p>
+    
+            <button onclick="reset_code()">Reset code</button>
+            <button onclick="save_snapshot()">Save snapshot</button>
+            <label for="snapshot">Carry on from a snapshot:</label>
+            <input type="file" id="snapshot" accept=".json" onchange="load_snapshot(this.files[0])">
+    
+            <table>
+                <tr>
+                    <th>Register</th>
+                    <th>Alternative name</th>
+                    <th>Value</th>
+                </tr>
+                <tr>
+                    <td>x0</td>
+                    <td>zero</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x1</td>
+                    <td>ra</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x2</td>
+                    <td>sp</td>
+                    <td>Can't read</td>
+                </tr>
+                <tr>
+                    <td>x3</td>
+                    <td>gp</td>
+                    <td>Can't read</td>
+                </tr>
+                <tr>
+                    <td>x4</td>
+                    <td>tp</td>
+                    <td>Can't read</td>
+                </tr>
+                <tr>
+                    <td>x5</td>
+                    <td>t0</td>
+                    <td><input id="x5" type="number" value="0" /></td>
+                </tr>
+                <tr>
+                    <td>x6</td>
+                    <td>t1</td>
+                    <td><input id="x6" type="number" value="0" /></td>
+                </tr>
+                <tr>
+                    <td>x7</td>
+                    <td>t2</td>
+                    <td><input id="x7" type="number" value="0" /></td>
+                </tr>
+                <tr>
+                    <td>x8</td>
+                    <td>s0/fp</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x9</td>
+                    <td>s1</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x10</td>
+                    <td>a0</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x11</td>
+                    <td>a1</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x12</td>
+                    <td>a2</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x13</td>
+                    <td>a3</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x14</td>
+                    <td>a4</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x15</td>
+                    <td>a5</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x16</td>
+                    <td>a6</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x17</td>
+                    <td>a7</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x18</td>
+                    <td>s2</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x19</td>
+                    <td>s3</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x20</td>
+                    <td>s4</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x21</td>
+                    <td>s5</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x22</td>
+                    <td>s6</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x23</td>
+                    <td>s7</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x24</td>
+                    <td>s8</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x25</td>
+                    <td>s9</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x26</td>
+                    <td>s10</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x27</td>
+                    <td>s11</td>
+                    <td>0</td>
+                </tr>
+                <tr>
+                    <td>x28</td>
+                    <td>t3</td>
+                    <td><input id="x28" type="number" value="0" /></td>
+                </tr>
+                <tr>
+                    <td>x29</td>
+                    <td>t4</td>
+                    <td><input id="x29" type="number" value="0" /></td>
+                </tr>
+                <tr>
+                    <td>x30</td>
+                    <td>t5</td>
+                    <td><input id="x30" type="number" value="0" /></td>
+                </tr>
+                <tr>
+                    <td>x31</td>
+                    <td>t6</td>
+                    <td><input id="x31" type="number" value="0" /></td>
+                </tr>
+            </table>
+        </div>
+    
+        <script>
+            let line = -1;
+            let session = null;
+    
+            //Thanks https://webtips.dev/add-line-numbers-to-html-textarea !
+            const textarea = document.querySelector('textarea')
+            const lineNumbers = document.querySelector('.line-numbers')
+    
+            textarea.addEventListener('keyup', event => {
+    
+                const numberOfLines = event.target.value.split('\n').length
+    
+                lineNumbers.innerHTML = Array(numberOfLines)
+                    .fill('<span class="line-number"></span>')
+                    .join('')
+                mark_breakpoints();
+            });
+
+            // Line → condition, null for none. Sent to the program once it exists.
+            const breakpoints = new Map();
+
+            lineNumbers.addEventListener('click', event => {
+                const lines = [...document.getElementsByClassName('line-number')];
+                const breakpointLine = lines.indexOf(event.target) + 1;
+                if (breakpointLine === 0) {
+                    return;
+                }
+                if (breakpoints.has(breakpointLine)) {
+                    breakpoints.delete(breakpointLine);
+                    send_breakpoint(breakpointLine, 'DELETE', null);
+                } else {
+                    const condition = event.shiftKey ? prompt('Stop when', 'x5 == 10') : null;
+                    breakpoints.set(breakpointLine, condition);
+                    send_breakpoint(breakpointLine, 'POST', condition);
+                }
+                mark_breakpoints();
+            });
+
+            function mark_breakpoints() {
+                const lines = document.getElementsByClassName('line-number');
+                for (let i = 0; i < lines.length; i++) {
+                    lines[i].classList.toggle('breakpoint', breakpoints.has(i + 1));
+                    lines[i].title = breakpoints.get(i + 1) ?? '';
+                }
+            }
+
+            function send_breakpoint(breakpointLine, method, condition) {
+                if (!session) {
+                    return Promise.resolve();
+                }
+                const query = condition ? `?if=${encodeURIComponent(condition)}` : '';
+                return fetch(`/${session}/breakpoints/${breakpointLine}${query}`, { method: method })
+                    .then(read_json)
+                    .then(data => {
+                        if (data.error) {
+                            errorElem.style.visibility = "visible";
+                            errorElem.children[0].textContent = data.error;
+                        }
+                    });
+            }
+
+            function continue_program() {
+                // The program is created by its first step
+                const started = session ? Promise.resolve() : step();
+                started.then(() => {
+                    if (!session) {
+                        return;
+                    }
+                    return fetch(`/${session}/continue`, { method: "POST" })
+                        .then(read_json)
+                        .then(data => {
+                            if (data.error) {
+                                errorElem.style.visibility = "visible";
+                                errorElem.children[0].textContent = data.error;
+                                return;
+                            }
+                            show_registers(data.registers);
+                            if (data.stop.reason === 'budget_exhausted') {
+                                show_budget_exhausted(data.stop.steps);
+                                return;
+                            }
+                            if (data.stop.reason === 'finished') {
+                                reset_code();
+                                errorElem.style.visibility = "visible";
+                                errorElem.children[0].textContent = "Program finished :)";
+                                return;
+                            }
+                            mark_executed(data.line);
+                            stoppedElem.textContent = data.stop.reason === 'breakpoint'
+                                ? `Stopped at the breakpoint on line ${data.stop.line}.`
+                                : `Stopped because ${data.stop.register} changed from ${data.stop.old} to ${data.stop.new}.`;
+                        });
+                });
+            }
+
+            function step_back() {
+                if (!session) {
+                    return;
+                }
+                fetch(`/${session}/back`, { method: "POST" })
+                    .then(read_json)
+                    .then(data => {
+                        if (data.error) {
+                            errorElem.style.visibility = "visible";
+                            errorElem.children[0].textContent = data.error;
+                            return;
+                        }
+                        errorElem.style.visibility = "";
+                        stoppedElem.textContent = "";
+                        executedElem.textContent = `Went back to step ${data.steps}`;
+                        show_registers(data.registers);
+                        mark_executed(data.line);
+                    });
+            }
+
+            function show_budget_exhausted(steps) {
+                reset_code();
+                errorElem.style.visibility = "visible";
+                errorElem.children[0].textContent = `Stopped after ${steps} steps, does the program loop forever?`;
+            }
+
+            function show_registers(registers) {
+                const table_rows = document.getElementsByTagName('tr');
+
+                for (let i = 2; i < table_rows.length; i++) {
+                    const thirdColumn = table_rows[i].children[2];
+
+                    if (thirdColumn.textContent === "Can't read") {
+                        continue;
+                    } else if (thirdColumn.textContent) {
+                        thirdColumn.textContent = registers[i - 2]; //x0 is zero, x1 is in index 0
+                    } else {
+                        thirdColumn.children[0].value = registers[i - 2]; // input elements
+                    }
+
+                }
+            }
+
+            function mark_executed(executed) {
+                line = executed;
+                const lines = document.getElementsByClassName('line-number');
+
+                for (const line of lines) {
+                    line.classList.remove('executed');
+                }
+
+                if (line > 0) {
+                    lines[line-1].classList.add('executed');
+                }
+            }
+    
+    
+            function save_snapshot() {
+                if (!session) {
+                    return;
+                }
+                fetch(`/${session}/snapshot`)
+                    .then(response => response.blob())
+                    .then(blob => {
+                        const link = document.createElement('a');
+                        link.href = URL.createObjectURL(blob);
+                        link.download = 'snapshot.json';
+                        link.click();
+                        URL.revokeObjectURL(link.href);
+                    });
+            }
+
+            function load_snapshot(file) {
+                file.text().then(json => {
+                    const snapshot = JSON.parse(json);
+                    const backend = document.getElementById('backend').value;
+                    return fetch(`/restore?backend=${backend}`, { method: "POST", body: json })
+                        .then(response => response.json())
+                        .then(data => {
+                            reset_code();
+                            if (data.error) {
+                                errorElem.style.visibility = "visible";
+                                errorElem.children[0].textContent = data.error;
+                                return;
+                            }
+                            textarea.value = snapshot.source.join('\n');
+                            breakpoints.clear();
+                            for (const breakpoint of snapshot.breakpoints) {
+                                breakpoints.set(breakpoint.line, breakpoint.condition);
+                            }
+                            textarea.dispatchEvent(new Event('keyup'));
+                            session = data.session;
+                            for (const element of document.getElementsByTagName('input')) {
+                                element.readOnly = true
+                            }
+                            executedElem.textContent = `Carrying on from step ${data.steps}`;
+                            show_registers(data.registers);
+                            mark_executed(data.line);
+                        });
+                });
+            }
+
+            // A session that expired or is gone is forgotten, the next step starts the program again
+            function read_json(response) {
+                if (response.status === 404 || response.status === 410) {
+                    session = null;
+                    reset_code();
+                }
+                return response.json();
+            }
+
+            function reset_code() {
+                if (session) {
+                    fetch(`/${session}`, { method: "DELETE" });
+                }
+                line = -1;
+                session = null;
+                for (const element of document.getElementsByTagName('input')) {
+                    element.readOnly = false
+                }
+                errorElem.style.visibility = "";
+                diagnosticsElem.replaceChildren();
+                executedElem.textContent = "";
+                stoppedElem.textContent = "";
+    
+                const lines = document.getElementsByClassName('line-number');
+                for (const line of lines) {
+                    line.classList.remove('executed');
+                    line.classList.remove('error-line');
+                }
+            }
+    
+            // Lists every problem with the source line and the offending part underlined
+            function show_errors(errors) {
+                const sourceLines = textarea.value.split('\n');
+                const lines = document.getElementsByClassName('line-number');
+                errorElem.style.visibility = "visible";
+                errorElem.children[0].textContent = `Found ${errors.length} problem(s):`;
+                diagnosticsElem.replaceChildren(...errors.map(error => {
+                    const [start, end] = error.columns;
+                    const hint = error.hint ? ` (${error.hint})` : '';
+                    const underline = ' '.repeat(start) + '^'.repeat(Math.max(end - start, 1));
+                    const pre = document.createElement('pre');
+                    pre.textContent = `Line ${error.line}: ${error.message}${hint}\n${sourceLines[error.line - 1] ?? ''}\n${underline}`;
+                    lines[error.line - 1]?.classList.add('error-line');
+                    return pre;
+                }));
+            }
+    
+            const errorElem = document.getElementById("error");
+            const diagnosticsElem = document.getElementById("diagnostics");
+            const executedElem = document.getElementById("executed");
+            const stoppedElem = document.getElementById("stopped");
+            function step() {
+                // The temporaries with an input in the table, by their id like x5
+                const registers = {};
+                for (const input of document.querySelectorAll('td input')) {
+                    registers[input.id] = Number(input.value);
+                }
+                const backend = document.getElementById('backend').value;
+                const body = session
+                    ? ''
+                    : JSON.stringify({ source: textarea.value, registers: registers, backend: backend });
+                const url = session ? `/${session}` : '/new';
+    
+                if (!session) {
+                    for (const element of document.getElementsByTagName('input')) {
+                        element.readOnly = true
+                    }
+                }
+    
+                return fetch(url, {
+                    method: "POST",
+                    body: body,
+                    headers: {
+                        "Accept": "application/json",
+                        "Content-Type": "application/json",
+                    },
+                }).then(response => {
+                    if (!response.ok) {
+                        return read_json(response).then(data => Promise.reject(new Error(data.error)));
+                    }
+                    return response.json();
+                }).then((data) => {
+                    if (data.errors) {
+                        reset_code();
+                        show_errors(data.errors);
+                        return;
+                    }
+                    if (data.done) {
+                        reset_code();
+                        errorElem.style.visibility = "visible";
+                        errorElem.children[0].textContent = "Program finished :)";
+                        return;
+                    }
+                    if (data.budget_exhausted) {
+                        show_budget_exhausted(data.steps);
+                        return;
+                    }
+                    if (data.error) {
+                        // A program that fails on its first step isn't kept, a later one carries on
+                        if (!session) {
+                            reset_code();
+                        }
+                        errorElem.style.visibility = "visible";
+                        errorElem.children[0].textContent = data.error;
+                        return;
+                    }
+                    const started = !session;
+                    session = data.session;
+                    errorElem.style.visibility = "";
+                    stoppedElem.textContent = "";
+    
+                    executedElem.textContent = `${data.encoding} ${data.instruction}`;
+                    show_registers(data.registers);
+                    mark_executed(data.line); //executed line
+                    if (started) {
+                        return Promise.all([...breakpoints].map(([breakpointLine, condition]) =>
+                            send_breakpoint(breakpointLine, 'POST', condition)));
+                    }
+                }).catch((err) => {
+                    console.error(err);
+                    errorElem.style.visibility = "visible";
+                    errorElem.children[0].textContent = err.toString();
+                });
+    
+            }
+        </script>
+    </body>
+    
+    </html>"#;
+
+    Response::new("text/html", contents.to_string())
+}
@@ -0,0 +1,213 @@
+//! What the page and the API send each other. Registers are always x1 to x31, lines start at 1.
+//!
+//! `POST /new` takes a [`NewProgram`]:
+//!
+//! ```json
+//! {"source":"addi t0, t0, 1\nsw t0, 0(t1)","registers":{"t0":41,"x6":268435456},"backend":"software"}
+//! ```
+//!
+//! `registers` and `backend` are optional. Only t0 to t6 can start with a value, by number or
+//! ABI name, the backend is `hardware`, `software` or `checked`.
+//!
+//! A step answers with one of
+//!
+//! ```json
+//! {"line":1,"registers":[0,...],"session":"3f9a0c21d4e87b65","instruction":"addi x5, x5, 1","encoding":"0x00128293"}
+//! {"budget_exhausted":true,"line":2,"steps":1000000}
+//! {"done":true}
+//! {"errors":[{"kind":"syntax","line":1,"columns":[0,4],"message":"...","hint":null}]}
+//! {"error":"Error on line 3: ..."}
+//! ```
+//!
+//! where `line` is the line that ran. Only a first step that ran answers with a `session`, a
+//! program that failed or was done straight away isn't kept. Continuing answers with the line that
+//! runs next and why it stopped, a `stop` with a `reason` of `finished`, `budget_exhausted` (with
+//! `steps`), `breakpoint` (with `line`) or `watchpoint` (with `register`, `old` and `new`).
+//! Anything that goes wrong is an `{"error": ...}`, a session that was idle for too long answers
+//! with a 410 and one that doesn't exist with a 404.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::assembly::{Diagnostic, Interpreter, StopReason};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewProgram {
+    pub source: String,
+    #[serde(default)]
+    pub registers: BTreeMap<String, i32>,
+    pub backend: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stepped {
+    pub line: usize,
+    pub registers: [i32; 31],
+    pub session: String,
+    pub instruction: String,
+    /// `0x` and eight hex digits.
+    pub encoding: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BudgetExhausted {
+    pub budget_exhausted: bool,
+    pub line: usize,
+    pub steps: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Done {
+    pub done: bool,
+}
+
+/// Where continuing stopped.
+#[derive(Debug, Serialize)]
+pub struct Stopped {
+    /// The line that runs next.
+    pub line: usize,
+    pub registers: [i32; 31],
+    pub session: String,
+    pub stop: Stop,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Stop {
+    Finished,
+    BudgetExhausted {
+        steps: u64,
+    },
+    Breakpoint {
+        line: usize,
+    },
+    Watchpoint {
+        register: String,
+        old: i32,
+        new: i32,
+    },
+}
+
+impl Stop {
+    pub fn new(reason: StopReason, interpreter: &Interpreter) -> Self {
+        match reason {
+            StopReason::Finished => Stop::Finished,
+            StopReason::BudgetExhausted => Stop::BudgetExhausted {
+                steps: interpreter.steps(),
+            },
+            StopReason::Breakpoint { line } => Stop::Breakpoint { line },
+            StopReason::Watchpoint { register, old, new } => Stop::Watchpoint {
+                register: register.to_string(),
+                old,
+                new,
+            },
+        }
+    }
+}
+
+/// Where a program is after stepping back or being restored.
+#[derive(Debug, Serialize)]
+pub struct Position {
+    /// The line that runs next.
+    pub line: usize,
+    pub registers: [i32; 31],
+    pub session: String,
+    pub steps: u64,
+}
+
+impl Position {
+    pub fn new(interpreter: &Interpreter, session: &str) -> Self {
+        Self {
+            line: interpreter.source_line().unwrap_or(0),
+            registers: interpreter.registers,
+            session: session.to_string(),
+            steps: interpreter.steps(),
+        }
+    }
+}
+
+/// Every breakpoint and watchpoint of a program.
+#[derive(Debug, Serialize)]
+pub struct Stops {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Breakpoint {
+    pub line: usize,
+    pub condition: Option<String>,
+}
+
+impl Stops {
+    pub fn new(interpreter: &Interpreter) -> Self {
+        Self {
+            breakpoints: interpreter
+                .breakpoints()
+                .iter()
+                .map(|(line, condition)| Breakpoint {
+                    line: *line,
+                    condition: condition.map(|condition| condition.to_string()),
+                })
+                .collect(),
+            watchpoints: interpreter
+                .watchpoints()
+                .iter()
+                .map(|register| register.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Answers ending a session with `DELETE /{session}`.
+#[derive(Debug, Serialize)]
+pub struct Ended {
+    pub ended: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Tracing {
+    pub tracing: bool,
+    /// Whether the trace filled up and left steps out.
+    pub truncated: bool,
+}
+
+/// Everything wrong with a program that doesn't assemble.
+#[derive(Debug, Serialize)]
+pub struct Errors {
+    pub errors: Vec<DiagnosticJson>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiagnosticJson {
+    pub kind: &'static str,
+    pub line: usize,
+    /// `[start, end)` byte offsets, starting at 0.
+    pub columns: [usize; 2],
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl Errors {
+    pub fn new(diagnostics: &[Diagnostic]) -> Self {
+        Self {
+            errors: diagnostics
+                .iter()
+                .map(|diagnostic| DiagnosticJson {
+                    kind: diagnostic.kind.as_str(),
+                    line: diagnostic.line,
+                    columns: [diagnostic.columns.start, diagnostic.columns.end],
+                    message: diagnostic.message.clone(),
+                    hint: diagnostic.hint.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Error {
+    pub error: String,
+}
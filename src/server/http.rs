@@ -0,0 +1,361 @@
+//! Just enough HTTP/1.1 for the page and its API: a request line, headers, a body as long as
+//! `Content-Length` says and keep-alive. No chunked bodies, nothing the page doesn't send.
+
+use std::io::{self, BufRead, Read, Write};
+
+use serde::Serialize;
+
+use crate::server::api::Error;
+
+/// Longest request line or header line.
+const MAX_LINE: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+/// Biggest body, a snapshot with the default memory is well under this.
+const MAX_BODY: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    /// Without the query.
+    pub path: String,
+    /// Still percent encoded, see [`Request::query`].
+    pub query: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Case insensitive, the first one if it's sent more than once.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Decoded value of a query parameter.
+    pub fn query(&self, name: &str) -> Option<String> {
+        self.query.split('&').find_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (key == name).then(|| percent_decode(value))
+        })
+    }
+
+    /// Path segments, `/2/breakpoints/4` is `["2", "breakpoints", "4"]` and `/` is `[]`.
+    pub fn segments(&self) -> Vec<&str> {
+        self.path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect()
+    }
+}
+
+/// Reads the next request on a connection, `Ok(None)` when the client closed it or went quiet
+/// before sending anything. A request that can't be handled is answered with the error response.
+pub fn read_request(reader: &mut impl BufRead) -> Result<Option<Request>, Response> {
+    let request_line = match read_line(reader) {
+        Ok(Some(line)) => line,
+        Ok(None) => return Ok(None),
+        Err(e) if is_timeout(&e) => return Ok(None),
+        Err(e) => return Err(Response::error(400, &e.to_string())),
+    };
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Response::error(
+            400,
+            &format!("'{request_line}' is not a request line"),
+        ));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(Response::error(
+            400,
+            &format!("{version} is not supported, only HTTP/1.0 and HTTP/1.1"),
+        ));
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut headers = Vec::new();
+    loop {
+        let line = match read_line(reader) {
+            Ok(Some(line)) => line,
+            Ok(None) => return Err(Response::error(400, "The request ended in its headers")),
+            Err(e) => return Err(Response::error(400, &e.to_string())),
+        };
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(Response::error(400, "Too many headers"));
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(Response::error(400, &format!("'{line}' is not a header")));
+        };
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        headers,
+        body: Vec::new(),
+    };
+    if request.header("Transfer-Encoding").is_some() {
+        return Err(Response::error(
+            400,
+            "Chunked bodies aren't supported, send a Content-Length",
+        ));
+    }
+    let length = match request.header("Content-Length") {
+        Some(length) => length
+            .parse::<usize>()
+            .map_err(|_| Response::error(400, &format!("'{length}' is not a Content-Length")))?,
+        None => 0,
+    };
+    if length > MAX_BODY {
+        return Err(Response::error(
+            413,
+            &format!("The body is {length} bytes, at most {MAX_BODY} are accepted"),
+        ));
+    }
+    request.body = vec![0; length];
+    reader
+        .read_exact(&mut request.body)
+        .map_err(|e| Response::error(400, &format!("Can't read the body: {e}")))?;
+    Ok(Some(request))
+}
+
+/// A line without its `\r\n`, `None` at the end of the stream.
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    Read::take(&mut *reader, MAX_LINE).read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "A line in the request is too long or was cut off",
+        ));
+    }
+    let line = String::from_utf8(line)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "The request isn't UTF-8"))?;
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Decodes `%20` style escapes and `+` for spaces in a query value.
+fn percent_decode(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' if rest.len() >= 2 => {
+                match std::str::from_utf8(&rest[..2])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(decoded) => {
+                        bytes.push(decoded);
+                        rest = &rest[2..];
+                    }
+                    None => bytes.push(byte),
+                }
+            }
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[derive(Debug)]
+pub struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+    /// Methods for the `Allow` header of a 405.
+    allow: Option<String>,
+}
+
+impl Response {
+    pub fn new(content_type: &'static str, body: String) -> Self {
+        Self {
+            status: 200,
+            content_type,
+            body,
+            allow: None,
+        }
+    }
+
+    pub fn json(value: &impl Serialize) -> Self {
+        // Structs with string keys and plain values, they always serialize
+        Self::new("application/json", serde_json::to_string(value).unwrap())
+    }
+
+    /// `{"error": message}` with `status`.
+    pub fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            ..Self::failed(message.to_string())
+        }
+    }
+
+    /// `{"error": message}` for a request that was fine but couldn't be done, the page shows the
+    /// message.
+    pub fn failed(message: String) -> Self {
+        Self::json(&Error { error: message })
+    }
+
+    pub fn not_found() -> Self {
+        Self::error(404, "Not found")
+    }
+
+    pub fn method_not_allowed(allowed: &[&str]) -> Self {
+        let allowed = allowed.join(", ");
+        Self {
+            allow: Some(allowed.clone()),
+            ..Self::error(405, &format!("Only {allowed} is allowed here"))
+        }
+    }
+
+    /// Always with `Connection: close`, every connection gets a single answer.
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            410 => "Gone",
+            413 => "Content Too Large",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        };
+        write!(
+            out,
+            "HTTP/1.1 {} {reason}\r\nContent-Length: {}\r\nContent-Type: {}\r\nConnection: close\r\n",
+            self.status,
+            self.body.len(),
+            self.content_type,
+        )?;
+        if let Some(allow) = &self.allow {
+            write!(out, "Allow: {allow}\r\n")?;
+        }
+        write!(out, "\r\n{}", self.body)?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_request, Request, Response, MAX_BODY, MAX_LINE};
+
+    fn read(request: &str) -> Result<Option<Request>, Response> {
+        read_request(&mut request.as_bytes())
+    }
+
+    fn status(request: &str) -> u16 {
+        read(request).unwrap_err().status
+    }
+
+    #[test]
+    fn request_with_a_body() {
+        let mut connection = "POST /new?backend=software HTTP/1.1\r\nContent-Length: 5\r\n\
+                              content-type: application/json\r\n\r\nhello\
+                              GET / HTTP/1.1\r\n\r\n"
+            .as_bytes();
+        let request = read_request(&mut connection).unwrap().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/new");
+        assert_eq!(request.query("backend").as_deref(), Some("software"));
+        assert_eq!(request.header("Content-Type"), Some("application/json"));
+        assert_eq!(request.body, b"hello");
+        // The body ends where its length says, whatever comes after is left for the next read
+        let next = read_request(&mut connection).unwrap().unwrap();
+        assert_eq!((next.method.as_str(), next.path.as_str()), ("GET", "/"));
+        assert!(read_request(&mut connection).unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_content_length() {
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nContent-Length: five\r\n\r\n"),
+            400
+        );
+        assert_eq!(status("POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n"), 400);
+        // Shorter than it says
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello"),
+            400
+        );
+    }
+
+    #[test]
+    fn missing_content_length_is_no_body() {
+        let request = read("POST / HTTP/1.1\r\n\r\nhello").unwrap().unwrap();
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn body_too_big() {
+        let request = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        assert_eq!(status(&request), 413);
+        let body = "x".repeat(MAX_BODY);
+        let request = format!("POST / HTTP/1.1\r\nContent-Length: {MAX_BODY}\r\n\r\n{body}");
+        assert_eq!(read(&request).unwrap().unwrap().body.len(), MAX_BODY);
+    }
+
+    #[test]
+    fn transfer_encoding_refused() {
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n"),
+            400
+        );
+    }
+
+    #[test]
+    fn lines_too_long() {
+        let path = "a".repeat(MAX_LINE as usize);
+        assert_eq!(status(&format!("GET /{path} HTTP/1.1\r\n\r\n")), 400);
+        let value = "a".repeat(MAX_LINE as usize);
+        assert_eq!(
+            status(&format!("GET / HTTP/1.1\r\nX-Long: {value}\r\n\r\n")),
+            400
+        );
+    }
+
+    #[test]
+    fn malformed_requests() {
+        assert_eq!(status("GET /\r\n\r\n"), 400);
+        assert_eq!(status("GET / HTTP/2\r\n\r\n"), 400);
+        assert_eq!(status("GET / HTTP/1.1\r\nno colon\r\n\r\n"), 400);
+        assert_eq!(status("GET / HTTP/1.1\r\nHost: x\r\n"), 400);
+        assert!(read("").unwrap().is_none());
+    }
+
+    #[test]
+    fn query_decoding() {
+        let request =
+            read("GET /3/breakpoints/4?if=x5+%3D%3D+10&to=7&empty&bad=%zz%4 HTTP/1.1\r\n\r\n")
+                .unwrap()
+                .unwrap();
+        assert_eq!(request.query("if").as_deref(), Some("x5 == 10"));
+        assert_eq!(request.query("to").as_deref(), Some("7"));
+        assert_eq!(request.query("empty").as_deref(), Some(""));
+        assert_eq!(request.query("bad").as_deref(), Some("%zz%4"));
+        assert_eq!(request.query("missing"), None);
+        assert_eq!(request.segments(), ["3", "breakpoints", "4"]);
+    }
+}
@@ -0,0 +1,216 @@
+//! The programs being run, each under an ID that's hard to guess so students don't step through
+//! each other's programs by accident.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::assembly::Interpreter;
+
+/// How many expired IDs are remembered to tell them apart from IDs that never existed.
+const REMEMBERED_EXPIRED: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// Programs that can run at the same time, each has its own memory and history.
+    pub max_sessions: usize,
+    /// A program nobody touched for this long makes way.
+    pub idle_timeout: Duration,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            max_sessions: 5,
+            idle_timeout: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+/// Why there is no session with an ID.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Missing {
+    /// It was idle for too long.
+    Expired,
+    /// It was ended or never existed.
+    Unknown,
+}
+
+#[derive(Debug)]
+struct Session {
+    /// Locked on its own, so a program that runs for a while only holds up its own requests.
+    interpreter: Arc<Mutex<Interpreter>>,
+    last_used: Instant,
+}
+
+#[derive(Debug)]
+pub struct Sessions {
+    config: SessionConfig,
+    sessions: HashMap<String, Session>,
+    /// Newest last.
+    expired: VecDeque<String>,
+    /// Randomly keyed, hashing a counter with it gives IDs that can't be guessed from each other.
+    ids: RandomState,
+    created: u64,
+}
+
+impl Sessions {
+    pub fn new(config: SessionConfig) -> Self {
+        Self {
+            config,
+            sessions: HashMap::new(),
+            expired: VecDeque::new(),
+            ids: RandomState::new(),
+            created: 0,
+        }
+    }
+
+    /// Starts a session and returns its ID, or `None` when there are already as many as allowed.
+    pub fn insert(&mut self, interpreter: Interpreter) -> Option<String> {
+        self.expire_idle();
+        if self.sessions.len() >= self.config.max_sessions {
+            return None;
+        }
+        let id = loop {
+            self.created += 1;
+            let mut hasher = self.ids.build_hasher();
+            hasher.write_u64(self.created);
+            let id = format!("{:016x}", hasher.finish());
+            if !self.sessions.contains_key(&id) {
+                break id;
+            }
+        };
+        self.sessions.insert(
+            id.clone(),
+            Session {
+                interpreter: Arc::new(Mutex::new(interpreter)),
+                last_used: Instant::now(),
+            },
+        );
+        Some(id)
+    }
+
+    /// The session's program, using it keeps it from expiring.
+    pub fn get(&mut self, id: &str) -> Result<Arc<Mutex<Interpreter>>, Missing> {
+        self.expire_idle();
+        match self.sessions.get_mut(id) {
+            Some(session) => {
+                session.last_used = Instant::now();
+                Ok(Arc::clone(&session.interpreter))
+            }
+            None if self.expired.iter().any(|expired| expired == id) => Err(Missing::Expired),
+            None => Err(Missing::Unknown),
+        }
+    }
+
+    /// Ends a session, its ID is unknown from then on.
+    pub fn remove(&mut self, id: &str) -> Result<(), Missing> {
+        self.get(id)?;
+        self.sessions.remove(id);
+        Ok(())
+    }
+
+    pub fn max_sessions(&self) -> usize {
+        self.config.max_sessions
+    }
+
+    fn expire_idle(&mut self) {
+        let timeout = self.config.idle_timeout;
+        let idle: Vec<String> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.last_used.elapsed() >= timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in idle {
+            self.sessions.remove(&id);
+            if self.expired.len() == REMEMBERED_EXPIRED {
+                self.expired.pop_front();
+            }
+            self.expired.push_back(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sessions(max_sessions: usize, idle_timeout: Duration) -> Sessions {
+        Sessions::new(SessionConfig {
+            max_sessions,
+            idle_timeout,
+        })
+    }
+
+    fn interpreter() -> Interpreter {
+        Interpreter::new(vec!["addi t0, t0, 1".to_string()]).unwrap()
+    }
+
+    #[test]
+    fn limit() {
+        let mut sessions = sessions(2, Duration::from_secs(60));
+        let first = sessions.insert(interpreter()).unwrap();
+        let second = sessions.insert(interpreter()).unwrap();
+        assert_ne!(first, second);
+        assert!(sessions.insert(interpreter()).is_none());
+        // Ending one makes room
+        sessions.remove(&first).unwrap();
+        assert!(sessions.insert(interpreter()).is_some());
+        assert!(sessions.get(&second).is_ok());
+    }
+
+    #[test]
+    fn idle_sessions_expire() {
+        let mut sessions = sessions(1, Duration::ZERO);
+        let id = sessions.insert(interpreter()).unwrap();
+        // Even when full, an idle session makes way for a new one
+        let next = sessions.insert(interpreter()).unwrap();
+        assert_eq!(sessions.get(&id).unwrap_err(), Missing::Expired);
+        assert_eq!(sessions.get(&next).unwrap_err(), Missing::Expired);
+        assert_eq!(sessions.remove(&id), Err(Missing::Expired));
+    }
+
+    #[test]
+    fn using_a_session_keeps_it() {
+        let mut sessions = sessions(1, Duration::from_millis(500));
+        let id = sessions.insert(interpreter()).unwrap();
+        for _ in 0..3 {
+            std::thread::sleep(Duration::from_millis(250));
+            assert!(sessions.get(&id).is_ok());
+        }
+        std::thread::sleep(Duration::from_millis(500));
+        assert_eq!(sessions.get(&id).unwrap_err(), Missing::Expired);
+    }
+
+    #[test]
+    fn expired_and_unknown() {
+        let mut sessions = sessions(5, Duration::from_secs(60));
+        assert_eq!(
+            sessions.get("0123456789abcdef").unwrap_err(),
+            Missing::Unknown
+        );
+        let id = sessions.insert(interpreter()).unwrap();
+        sessions.remove(&id).unwrap();
+        // Ended on purpose, not expired
+        assert_eq!(sessions.get(&id).unwrap_err(), Missing::Unknown);
+        assert_eq!(sessions.remove(&id), Err(Missing::Unknown));
+    }
+
+    #[test]
+    fn remembered_expired_ids_are_capped() {
+        let mut sessions = sessions(1, Duration::ZERO);
+        let ids: Vec<String> = (0..REMEMBERED_EXPIRED + 1)
+            .map(|_| sessions.insert(interpreter()).unwrap())
+            .collect();
+        // Expires the last one too
+        let _ = sessions.get(&ids[0]);
+        assert_eq!(sessions.expired.len(), REMEMBERED_EXPIRED);
+        assert_eq!(sessions.get(&ids[0]).unwrap_err(), Missing::Unknown);
+        for id in &ids[1..] {
+            assert_eq!(sessions.get(id).unwrap_err(), Missing::Expired);
+        }
+    }
+}